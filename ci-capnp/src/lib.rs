@@ -55,12 +55,18 @@ pub enum Container {
     Included,
     OpenError(String),
     ReadError(String),
+    /// Only a record of what the format said about a container whose entries follow.
+    Unpacked,
 }
 
 #[derive(Clone, Debug)]
 pub struct FileEntry {
     pub len: u64,
     pub paths: Vec<String>,
+    /// The original bytes of each path, where `paths` only has a (lossy) decoding of them.
+    pub raw_paths: Vec<Option<Vec<u8>>>,
+    /// (algorithm, value), e.g. ("sha256", [..]), if the writer was asked to hash the content.
+    pub digests: Vec<(String, Vec<u8>)>,
     pub content_follows: bool,
     pub meta: Meta,
 }
@@ -75,4 +81,6 @@ pub struct Meta {
     pub item_type: ItemType,
     pub container: Container,
     pub xattrs: HashMap<String, Vec<u8>>,
+    /// e.g. ("zip.comment", ..); things the archive says, which a filesystem couldn't.
+    pub format_fields: HashMap<String, Vec<u8>>,
 }
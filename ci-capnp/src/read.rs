@@ -32,6 +32,25 @@ pub fn read_entry<R: io::Read>(from: R) -> capnp::Result<Option<FileEntry>> {
         paths.push(entry_paths.get(i)?.to_string());
     }
 
+    let mut raw_paths = vec![None; paths.len()];
+    let entry_raw_paths = entry.get_raw_paths()?;
+    for i in 0..entry_raw_paths.len().min(entry_paths_len) {
+        let raw = entry_raw_paths.get(i)?;
+        if !raw.is_empty() {
+            raw_paths[i as usize] = Some(raw.to_vec());
+        }
+    }
+
+    let entry_digests = entry.get_digests()?;
+    let mut digests = Vec::with_capacity(entry_digests.len() as usize);
+    for i in 0..entry_digests.len() {
+        let digest = entry_digests.get(i);
+        digests.push((
+            digest.get_algorithm()?.to_string(),
+            digest.get_value()?.to_vec(),
+        ));
+    }
+
     let entry_xattrs = entry.get_xattrs()?;
     let entry_xattrs_len = entry_xattrs.len();
 
@@ -41,6 +60,14 @@ pub fn read_entry<R: io::Read>(from: R) -> capnp::Result<Option<FileEntry>> {
         let xattr = entry_xattrs.get(i);
         xattrs.insert(xattr.get_name()?.to_string(), xattr.get_value()?.to_vec());
     }
+
+    let entry_fields = entry.get_format_fields()?;
+    let mut format_fields = HashMap::with_capacity(entry_fields.len() as usize);
+    for i in 0..entry_fields.len() {
+        let field = entry_fields.get(i);
+        format_fields.insert(field.get_name()?.to_string(), field.get_value()?.to_vec());
+    }
+
     let meta = Meta {
         atime: entry.get_atime(),
         mtime: entry.get_mtime(),
@@ -91,13 +118,17 @@ pub fn read_entry<R: io::Read>(from: R) -> capnp::Result<Option<FileEntry>> {
             entry::container::Which::Included(()) => Container::Included,
             entry::container::Which::OpenError(msg) => Container::OpenError(msg?.to_string()),
             entry::container::Which::ReadError(msg) => Container::ReadError(msg?.to_string()),
+            entry::container::Which::Unpacked(()) => Container::Unpacked,
         },
         xattrs,
+        format_fields,
     };
 
     Ok(Some(FileEntry {
         len: entry.get_len(),
         paths,
+        raw_paths,
+        digests,
         meta,
         content_follows: matches!(
             entry.get_content().which()?,
@@ -63,6 +63,19 @@ pub fn write_meta(meta: &crate::Meta, entry: &mut entry::Builder, size: u64) ->
         }
     }
 
+    {
+        let mut container = entry.reborrow().get_container();
+
+        use crate::Container::*;
+        match meta.container {
+            Unrecognised => container.set_unrecognised(()),
+            Included => container.set_included(()),
+            OpenError(ref msg) => container.set_open_error(msg.as_str()),
+            ReadError(ref msg) => container.set_read_error(msg.as_str()),
+            Unpacked => container.set_unpacked(()),
+        }
+    }
+
     {
         assert!(meta.xattrs.len() <= std::u32::MAX as usize);
         let mut xattrs = entry.reborrow().init_xattrs(meta.xattrs.len() as u32);
@@ -75,5 +88,19 @@ pub fn write_meta(meta: &crate::Meta, entry: &mut entry::Builder, size: u64) ->
         }
     }
 
+    {
+        assert!(meta.format_fields.len() <= std::u32::MAX as usize);
+        let mut fields = entry
+            .reborrow()
+            .init_format_fields(meta.format_fields.len() as u32);
+        let mut names: Vec<&String> = meta.format_fields.keys().collect();
+        names.sort();
+        for (i, name) in names.into_iter().enumerate() {
+            let mut row = fields.reborrow().get(i as u32);
+            row.set_name(name);
+            row.set_value(&meta.format_fields[name]);
+        }
+    }
+
     Ok(())
 }
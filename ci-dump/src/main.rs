@@ -67,17 +67,28 @@ fn main() {
         let transients = !(drop_local_fs_details && 1 == entry.paths.len());
 
         println!(" - paths:");
-        for path in entry.paths {
-            println!("          - {}", path);
+        for (path, raw) in entry.paths.iter().zip(&entry.raw_paths) {
+            match raw {
+                Some(raw) => println!("          - {} (raw: {:?})", path, raw),
+                None => println!("          - {}", path),
+            }
         }
 
         println!("   type:  {:?}", entry.meta.item_type);
 
+        if let ci_capnp::Container::Unpacked = entry.meta.container {
+            println!("   wrap:  {:?}", entry.meta.container);
+        }
+
         if 0 != entry.len {
             println!("   wrap:  {:?}", entry.meta.container);
             println!("   data:  {:?}", entry.content_follows);
             println!("   size:  {}", entry.len);
             println!("   crc:   {:08x}", item.crc);
+            for (algorithm, value) in &entry.digests {
+                let hex: String = value.iter().map(|b| format!("{:02x}", b)).collect();
+                println!("   {}: {}", algorithm, hex);
+            }
         }
 
         if transients {
@@ -121,6 +132,15 @@ fn main() {
                 println!("     {}: {:?}", key, entry.meta.xattrs[key]);
             }
         }
+
+        if !entry.meta.format_fields.is_empty() {
+            println!("   fields:");
+            let mut keys: Vec<&String> = entry.meta.format_fields.keys().collect();
+            keys.sort();
+            for key in keys {
+                println!("     {}: {:?}", key, entry.meta.format_fields[key]);
+            }
+        }
     }
 }
 
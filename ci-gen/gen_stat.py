@@ -21,6 +21,10 @@ pub struct Stat {
     pub ctime: i64,
     pub ctime_nano: i64,
     pub mode: u32,
+    pub rdev: u64,
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
 }
 
 impl Stat {
@@ -37,6 +41,10 @@ for os in OSES:
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }}
     }}
 """.format(os)
@@ -50,6 +58,10 @@ F += """
             ctime: 0,
             ctime_nano: 0,
             mode: 0,
+            rdev: 0,
+            dev: 0,
+            ino: 0,
+            nlink: 0,
         }}
     }}
 }}
@@ -16,6 +16,7 @@ T=$(mktemp -d)
     tar zcf "${O}/simple.tar.gz" *
     tar jcf "${O}/simple.tar.bz2" *
     tar Jcf "${O}/simple.tar.xz" *
+    tar --zstd -cf "${O}/simple.tar.zst" *
 )
 
 # dd if=/dev/urandom of=crap bs=1 count=3k
@@ -37,3 +38,661 @@ dd if=/dev/zero bs=1 conv=notrunc count=2 seek=5000 of="${O}/byte_flip.tar.bz2"
 dd if=/dev/zero bs=1 conv=notrunc count=2 seek=5000 of="${O}/byte_flip.tar.xz"
 
 rm -rf "$T"
+
+# devices can't be created without root, so build this one by hand
+python3 - "${O}/types.tar" <<'PY'
+import io, sys, tarfile
+
+def info(name, type, mode=0o644, **kw):
+    t = tarfile.TarInfo(name)
+    t.type = type
+    t.mtime = 1500000000
+    t.uname = t.gname = 'root'
+    t.mode = mode
+    for k, v in kw.items():
+        setattr(t, k, v)
+    return t
+
+with tarfile.open(sys.argv[1], 'w', format=tarfile.GNU_FORMAT) as tf:
+    tf.addfile(info('dir/', tarfile.DIRTYPE, mode=0o755))
+    tf.addfile(info('dir/file', tarfile.REGTYPE, size=9), io.BytesIO(b'123456789'))
+    tf.addfile(info('dir/empty', tarfile.REGTYPE))
+    tf.addfile(info('dir/hard', tarfile.LNKTYPE, linkname='dir/file'))
+    tf.addfile(info('dir/soft', tarfile.SYMTYPE, mode=0o777, linkname='file'))
+    tf.addfile(info('dir/fifo', tarfile.FIFOTYPE))
+    tf.addfile(info('dir/null', tarfile.CHRTYPE, devmajor=1, devminor=3))
+    tf.addfile(info('dir/loop6', tarfile.BLKTYPE, devmajor=7, devminor=6))
+PY
+
+# an initramfs with early microcode: a plain newc archive, then a compressed one
+python3 - "${O}/initramfs.cpio" <<'PY'
+import gzip, sys
+
+def newc(entries):
+    out = b''
+    for name, mode, ino, nlink, data, rdev in entries + [('TRAILER!!!', 0, 0, 1, b'', (0, 0))]:
+        name = name.encode() + b'\0'
+        fields = [ino, mode, 0, 0, nlink, 1500000000, len(data), 0, 0, rdev[0], rdev[1], len(name), 0]
+        hdr = b'070701' + b''.join(b'%08x' % f for f in fields) + name
+        hdr += b'\0' * (-len(hdr) % 4)
+        out += hdr + data + b'\0' * (-len(data) % 4)
+    return out + b'\0' * (-len(out) % 512)
+
+# each archive numbers its inodes from scratch, so links don't reach between them
+early = newc([
+    ('kernel', 0o40755, 1, 2, b'', (0, 0)),
+    ('kernel/microcode.bin', 0o100644, 2, 1, b'123456789', (0, 0)),
+    ('kernel/firmware', 0o100644, 3, 2, b'firmware', (0, 0)),
+    # its other name is missing
+    ('kernel/orphan', 0o100644, 6, 2, b'', (0, 0)),
+])
+main = newc([
+    ('.', 0o40755, 1, 3, b'', (0, 0)),
+    ('bin', 0o40755, 2, 2, b'', (0, 0)),
+    # newc only stores a hardlink's content with its last name
+    ('bin/busybox', 0o100755, 3, 2, b'', (0, 0)),
+    ('bin/sh', 0o100755, 3, 2, b'busybox!', (0, 0)),
+    ('bin/ash', 0o100755, 6, 2, b'ash!', (0, 0)),
+    ('init', 0o120777, 4, 1, b'bin/sh', (0, 0)),
+    ('console', 0o20600, 5, 1, b'', (5, 1)),
+])
+with open(sys.argv[1], 'wb') as f:
+    f.write(early)
+    f.write(gzip.compress(main, mtime=0))
+PY
+
+# no rpmbuild here, either: a lead, an empty signature, a header, and a gzip'd newc payload
+python3 - "${O}/simple.rpm" <<'PY'
+import gzip, hashlib, struct, sys
+
+def newc(entries):
+    out = b''
+    for ino, (name, mode, data) in enumerate(entries + [('TRAILER!!!', 0, b'')]):
+        name = name.encode() + b'\0'
+        fields = [ino, mode, 0, 0, 1, 1500000000, len(data), 0, 0, 0, 0, len(name), 0]
+        hdr = b'070701' + b''.join(b'%08x' % f for f in fields) + name
+        hdr += b'\0' * (-len(hdr) % 4)
+        out += hdr + data + b'\0' * (-len(data) % 4)
+    return out
+
+def header(tags, aligned):
+    index, store = b'', b''
+    for tag, kind, values in tags:
+        if 4 == kind:
+            store += b'\0' * (-len(store) % 4)
+            data = b''.join(struct.pack('>I', v) for v in values)
+        else:
+            data = b''.join(v.encode() + b'\0' for v in values)
+        index += struct.pack('>IIII', tag, kind, len(store), len(values))
+        store += data
+    out = b'\x8e\xad\xe8\x01\0\0\0\0' + struct.pack('>II', len(tags), len(store)) + index + store
+    return out + b'\0' * (-len(out) % 8 if aligned else 0)
+
+files = [('./etc', 0o40755, b''), ('./etc/foo.conf', 0o100644, b'123456789')]
+lead = b'\xed\xab\xee\xdb\x03\x00' + b'\0' * 90
+tags = [
+    (1000, 6, ['simple']),
+    (1001, 6, ['1.0']),
+    (1002, 6, ['1']),
+    (1022, 6, ['noarch']),
+    (1035, 8, ['', hashlib.sha256(files[1][2]).hexdigest()]),
+    (1039, 8, ['root', 'daemon']),
+    (1040, 8, ['root', 'daemon']),
+    (1116, 4, [0, 1]),
+    (1117, 8, ['etc', 'foo.conf']),
+    (1118, 8, ['/', '/etc/']),
+    (5011, 4, [8]),
+]
+with open(sys.argv[1], 'wb') as f:
+    f.write(lead + header([], True) + header(tags, False))
+    f.write(gzip.compress(newc(files), mtime=0))
+PY
+
+# isos, by hand: one with Rock Ridge (and Joliet) names, one with only Joliet
+python3 - "${O}" <<'PY'
+import struct, sys
+
+SECTOR = 2048
+WHEN = bytes([117, 7, 14, 3, 40, 0, 4])
+
+def both32(v):
+    return struct.pack('<I', v) + struct.pack('>I', v)
+
+def record(extent, size, flags, name, su=b''):
+    body = both32(extent) + both32(size) + WHEN + bytes([flags, 0, 0]) + b'\1\0\0\1'
+    body += bytes([len(name)]) + name + b'\0' * (1 - len(name) % 2) + su
+    body = bytes([0]) + body
+    body += b'\0' * ((len(body) + 1) % 2)
+    return bytes([len(body) + 1]) + body
+
+def su(tag, data):
+    return tag + bytes([len(data) + 4, 1]) + data
+
+def px(mode, uid=0, gid=0, nlink=1):
+    return su(b'PX', both32(mode) + both32(nlink) + both32(uid) + both32(gid))
+
+def nm(name):
+    return su(b'NM', b'\0' + name)
+
+def rr(name, mode, *extra, uid=0):
+    return nm(name) + px(mode, uid=uid) + su(b'TF', b'\x02' + WHEN) + b''.join(extra)
+
+def sector(data):
+    assert len(data) <= SECTOR
+    return data + b'\0' * (SECTOR - len(data))
+
+def descriptor(kind, root, escape=b''):
+    d = bytearray(SECTOR)
+    d[0] = kind
+    d[1:7] = b'CD001\1'
+    d[80:88] = both32(24)
+    d[88:88 + len(escape)] = escape
+    d[128:132] = struct.pack('<H', SECTOR) + struct.pack('>H', SECTOR)
+    d[156:190] = root
+    return bytes(d)
+
+def joliet(name):
+    return name.encode('utf-16-be')
+
+def image(rock_ridge):
+    r = rock_ridge
+    sp = su(b'SP', b'\xbe\xef\0') if r else b''
+    root = (
+        record(19, SECTOR, 2, b'\0', sp + (px(0o40755) if r else b''))
+        + record(19, SECTOR, 2, b'\1')
+        + record(20, SECTOR, 2, b'DIR', rr(b'dir', 0o40755) if r else b'')
+        + record(23, 9, 0, b'FILE.TXT;1', rr(b'foo.txt', 0o100644, uid=1000) if r else b'')
+        + (record(0, 0, 0, b'LINK.;1', rr(b'link', 0o120777, su(b'SL', b'\0\x08\0\0\3etc\0\6passwd'))) if r else b'')
+        + (record(0, 0, 0, b'NULL.;1', rr(b'null', 0o20666, su(b'PN', both32(1) + both32(3)))) if r else b'')
+    )
+    subdir = (
+        record(20, SECTOR, 2, b'\0')
+        + record(19, SECTOR, 2, b'\1')
+        + record(23, 9, 0, b'BAR.;1', rr(b'bar', 0o100600) if r else b'')
+    )
+    jroot = (
+        record(21, SECTOR, 2, b'\0')
+        + record(21, SECTOR, 2, b'\1')
+        + record(22, SECTOR, 2, joliet('Dir'))
+        + record(23, 9, 0, joliet('Foo.txt;1'))
+    )
+    jdir = record(22, SECTOR, 2, b'\0') + record(21, SECTOR, 2, b'\1') + record(23, 9, 0, joliet('Bar;1'))
+
+    out = b'\0' * (16 * SECTOR)
+    out += descriptor(1, record(19, SECTOR, 2, b'\0'))
+    out += descriptor(2, record(21, SECTOR, 2, b'\0'), b'%/E')
+    out += sector(b'\xffCD001\1')
+    for data in [root, subdir, jroot, jdir, b'123456789']:
+        out += sector(data)
+    return out
+
+with open(sys.argv[1] + '/simple.iso', 'wb') as f:
+    f.write(image(True))
+with open(sys.argv[1] + '/joliet.iso', 'wb') as f:
+    f.write(image(False))
+PY
+
+# no mksquashfs either: a gzip'd squashfs, with a sparse block, fragments, a hardlink and an xattr
+python3 - "${O}/simple.squashfs" <<'PY'
+import struct, sys, zlib
+
+BS = 4096
+WHEN = 1500000000
+
+def meta(data):
+    c = zlib.compress(data)
+    return struct.pack('<H', len(c)) + c
+
+big_block = zlib.compress(b'a' * BS)
+tails = b'123456789' + b'hello, world\n' + b'xyz'
+frag_block = zlib.compress(tails)
+data = big_block + frag_block
+big_start, frag_start = 96, 96 + len(big_block)
+
+def header(kind, perm, number, uid=0):
+    return struct.pack('<HHHHII', kind, perm, uid, 0, WHEN, number)
+
+# (name, number, size, dir type, inode body); sizes are needed before the listings exist
+files = {
+    'bar': (3, 2, lambda: header(2, 0o644, 3) + struct.pack('<IIII', 0, 0, 0, 9)),
+    'big': (4, 2, lambda: header(2, 0o644, 4) + struct.pack('<IIII', big_start, 0, 22, 2 * BS + 3)
+            + struct.pack('<II', 0, len(big_block))),
+    'foo': (5, 2, lambda: header(9, 0o600, 5, uid=1) + struct.pack('<QQQIIII', 0, 13, 0, 2, 0, 9, 0)),
+    'link': (6, 3, lambda: header(3, 0o777, 6) + struct.pack('<II', 1, 3) + b'foo'),
+    'null': (7, 5, lambda: header(5, 0o666, 7) + struct.pack('<II', 1, 0x103)),
+}
+
+inodes, offsets = b'', {}
+for name in ['bar', 'big', 'foo', 'link', 'null']:
+    offsets[name] = len(inodes)
+    inodes += files[name][2]()
+offsets['hard'] = offsets['foo']
+files['hard'] = files['foo']
+
+def listing(names, base):
+    out = struct.pack('<III', len(names) - 1, 0, base)
+    for name in names:
+        number, kind = files[name][0], files[name][1]
+        out += struct.pack('<HhHH', offsets[name], number - base, kind, len(name) - 1) + name.encode()
+    return out
+
+sub = listing(['bar'], 3)
+offsets['dir'] = len(inodes)
+files['dir'] = (2, 1, None)
+inodes += header(1, 0o755, 2) + struct.pack('<IIHHI', 0, 2, len(sub) + 3, 0, 1)
+root = listing(['big', 'dir', 'foo', 'hard', 'link', 'null'], 2)
+root_offset = len(inodes)
+inodes += header(1, 0o755, 1) + struct.pack('<IIHHI', 0, 3, len(root) + 3, len(sub), 8)
+
+kv = struct.pack('<HH', 0, 7) + b'comment' + struct.pack('<I', 2) + b'hi'
+
+out = bytearray(96) + data
+tables = {}
+for name, content in [('inodes', inodes), ('dirs', sub + root)]:
+    tables[name] = len(out)
+    out += meta(content)
+# the lookup tables are metadata blocks, then a list of pointers to them
+for name, content in [
+    ('frags', struct.pack('<QII', frag_start, len(frag_block), 0)),
+    ('ids', struct.pack('<II', 0, 1000)),
+    ('xattr ids', struct.pack('<QII', 0, 1, len(kv))),
+]:
+    tables[name] = len(out)
+    out += meta(content)
+    tables[name + ' pointers'] = len(out)
+    out += struct.pack('<Q', tables[name])
+kv_start = len(out)
+out += meta(kv)
+xattrs = len(out)
+out += struct.pack('<QII', kv_start, 1, 0) + struct.pack('<Q', tables['xattr ids'])
+
+out[:96] = b'hsqs' + struct.pack(
+    '<IIIIHHHHHHQQQQQQQQ', 7, WHEN, BS, 1, 1, 12, 0, 2, 4, 0,
+    root_offset, len(out), tables['ids pointers'], xattrs, tables['inodes'], tables['dirs'],
+    tables['frags pointers'], 2 ** 64 - 1)
+out += b'\0' * (-len(out) % 4096)
+with open(sys.argv[1], 'wb') as f:
+    f.write(out)
+PY
+
+# no mkfs.vfat or mkfs.exfat: a disk image with a FAT12 partition, and a bare exFAT filesystem
+python3 - "${O}" <<'PY'
+import struct, sys
+
+DATE = (37 << 9) | (7 << 5) | 14
+TIME = lambda hour: (hour << 11) | (40 << 5)
+
+def fat_entry(name, attr, cluster, size, lower=0):
+    return name + struct.pack('<BBBHHHHHHHI', attr, lower, 0, 0, DATE, DATE, 0, TIME(2), DATE, cluster, size)
+
+def lfn_entries(name, short):
+    checksum = 0
+    for b in short:
+        checksum = (((checksum & 1) << 7) + (checksum >> 1) + b) & 0xff
+    units = name.encode('utf-16-le') + b'\0\0'
+    units += b'\xff' * (-len(units) % 26)
+    parts = [units[i:i + 26] for i in range(0, len(units), 26)]
+    out = b''
+    for i, part in reversed(list(enumerate(parts))):
+        order = (i + 1) | (0x40 if i == len(parts) - 1 else 0)
+        out += bytes([order]) + part[:10] + bytes([0x0f, 0, checksum]) + part[10:22] + b'\0\0' + part[22:]
+    return out
+
+def fat12(table):
+    out = bytearray((len(table) * 3 + 1) // 2)
+    for i, v in enumerate(table):
+        at = i * 3 // 2
+        pair = struct.unpack('<H', out[at:at + 2])[0]
+        pair |= v << 4 if i % 2 else v
+        out[at:at + 2] = struct.pack('<H', pair)
+    return bytes(out)
+
+SECTOR = 512
+boot = bytearray(SECTOR)
+boot[0:3] = b'\xeb\x3c\x90'
+boot[3:11] = b'MSWIN4.1'
+boot[11:36] = struct.pack('<HBHBHHBHHHII', SECTOR, 1, 1, 2, 16, 64, 0xf8, 1, 32, 2, 0, 0)
+boot[54:62] = b'FAT12   '
+boot[510:512] = b'\x55\xaa'
+
+long_short = b'LONGFI~1TXT'
+root = lfn_entries('Long File Name.txt', long_short) + fat_entry(long_short, 0x20, 2, 9) + fat_entry(b'DIR        ', 0x10, 3, 0)
+sub = fat_entry(b'.          ', 0x10, 3, 0) + fat_entry(b'..         ', 0x10, 0, 0) + fat_entry(b'BAR        ', 0x20, 4, 600, lower=0x08)
+table = fat12([0xff8, 0xfff, 0xfff, 0xfff, 5, 0xfff])
+
+fs = bytes(boot)
+fs += (table + bytes(SECTOR - len(table))) * 2
+fs += root + bytes(SECTOR - len(root))
+for data in [b'123456789', sub, b'x' * SECTOR, b'y' * 88]:
+    fs += data + bytes(-len(data) % SECTOR)
+fs += bytes(64 * SECTOR - len(fs))
+
+mbr = bytearray(SECTOR)
+mbr[446:462] = struct.pack('<B3sB3sII', 0, b'\0\0\0', 0x01, b'\0\0\0', 1, 64)
+mbr[510:512] = b'\x55\xaa'
+with open(sys.argv[1] + '/fat.img', 'wb') as f:
+    f.write(bytes(mbr) + fs)
+
+# exfat: clusters are sectors, the FAT is at sector 24, and the heap (cluster 2) at 32;
+# contiguous runs, like `sub`, are left out of the FAT, as real implementations do
+def exfat_set(name, attr, cluster, size, valid, contiguous):
+    units = name.encode('utf-16-le')
+    names = [units[i:i + 30] for i in range(0, len(units), 30)]
+    stamp = struct.pack('<HH', TIME(3), DATE)
+    out = struct.pack('<BBHH2s', 0x85, 1 + len(names), 0, attr, b'') + stamp * 3 + bytes([0, 0, 0x84, 0x84, 0x84]) + bytes(7)
+    out += struct.pack('<BBBBHH', 0xc0, 1 | (2 if contiguous else 0), 0, len(name), 0, 0)
+    out += struct.pack('<QIIQ', valid, 0, cluster, size)
+    for part in names:
+        out += b'\xc1\0' + part + bytes(30 - len(part))
+    return out
+
+boot = bytearray(SECTOR)
+boot[0:3] = b'\xeb\x76\x90'
+boot[3:11] = b'EXFAT   '
+boot[64:112] = struct.pack('<QQIIIIIIHHBBBB', 0, 40, 24, 1, 32, 8, 2, 0, 0x100, 0, 9, 0, 1, 0x80)
+boot[510:512] = b'\x55\xaa'
+
+root = b'\x81' + bytes(31) + exfat_set('hello.txt', 0x20, 3, 9, 9, True) + exfat_set('sub', 0x10, 4, SECTOR, SECTOR, True)
+sub = exfat_set('x', 0x20, 5, 5, 2, False)
+table = struct.pack('<6I', 0xfffffff8, 0xffffffff, 0xffffffff, 0, 0, 0xffffffff)
+
+fs = bytes(boot) + bytes(23 * SECTOR) + table + bytes(SECTOR - len(table)) + bytes(7 * SECTOR)
+for data in [root, b'123456789', sub, b'ab']:
+    fs += data + bytes(-len(data) % SECTOR)
+fs += bytes(40 * SECTOR - len(fs))
+with open(sys.argv[1] + '/exfat.img', 'wb') as f:
+    f.write(fs)
+PY
+
+# no 7z either: a solid LZMA2 block, BCJ+LZMA, bzip2 and deflate folders, under an LZMA header;
+# and one which claims to be encrypted
+python3 - "${O}" <<'PY'
+import bz2, lzma, struct, sys, zlib
+
+MTIME = (1500000000 + 11644473600) * 10000000
+LZMA1 = lambda dict_size: {'id': lzma.FILTER_LZMA1, 'dict_size': dict_size}
+
+def num(v):
+    for n in range(9):
+        if n == 8 or v < 1 << (8 * n + 7 - n):
+            break
+    if n == 8:
+        return b'\xff' + v.to_bytes(8, 'little')
+    return bytes([(0xff00 >> n) & 0xff | v >> (8 * n)]) + (v & ((1 << 8 * n) - 1)).to_bytes(n, 'little')
+
+def bits(flags):
+    out = bytearray((len(flags) + 7) // 8)
+    for i, flag in enumerate(flags):
+        if flag:
+            out[i // 8] |= 0x80 >> (i % 8)
+    return bytes(out)
+
+def folder(coders):
+    out = num(len(coders))
+    for id, props in coders:
+        out += bytes([len(id) | (0x20 if props else 0)]) + id + (num(len(props)) + props if props else b'')
+    # a chain: each coder reads the next one's output, and the last reads the packed stream
+    for i in range(len(coders) - 1):
+        out += num(i) + num(i + 1)
+    return out
+
+def streams(pack_pos, packed, folders, substreams=None):
+    out = b'\x06' + num(pack_pos) + num(len(packed)) + b'\x09' + b''.join(num(len(p)) for p in packed) + b'\x00'
+    out += b'\x07\x0b' + num(len(folders)) + b'\x00' + b''.join(folder(c) for c, _ in folders)
+    out += b'\x0c' + b''.join(num(size) for c, size in folders for _ in c) + b'\x00'
+    if substreams:
+        out += b'\x08\x0d' + b''.join(num(len(s)) for s in substreams)
+        out += b'\x09' + b''.join(num(size) for s in substreams for size in s[:-1]) + b'\x00'
+    return out + b'\x00'
+
+def files(entries):
+    prop = lambda id, data: num(id) + num(len(data)) + data
+    empty = [data is None for _, data, _ in entries]
+    out = b'\x05' + num(len(entries))
+    out += prop(0x0e, bits(empty))
+    out += prop(0x0f, bits([not attr & 0x10 for (_, data, attr) in entries if data is None]))
+    out += prop(0x11, b'\x00' + ''.join(name + '\0' for name, _, _ in entries).encode('utf-16-le'))
+    out += prop(0x14, b'\x01\x00' + struct.pack('<Q', MTIME) * len(entries))
+    out += prop(0x15, b'\x01\x00' + b''.join(struct.pack('<I', attr) for _, _, attr in entries))
+    return out + b'\x00'
+
+def archive(packed, header):
+    start = struct.pack('<QQI', len(packed), len(header), zlib.crc32(header))
+    return b'7z\xbc\xaf\x27\x1c\x00\x04' + struct.pack('<I', zlib.crc32(start)) + start + packed + header
+
+def unix(mode):
+    return 0x8000 | mode << 16 | (0x10 if mode & 0o40000 else 0x20)
+
+prog = b''.join(b'\x90' * 7 + b'\xe8' + struct.pack('<i', 0x100 - i * 12) for i in range(40))
+contents = [
+    ('a/bar', b'123456789', unix(0o100644)),
+    ('foo', b'123456789' * 3, 0x20),
+    ('link', b'foo', unix(0o120777)),
+    ('prog', prog, 0x20),
+    ('b.txt', b'bzip2\n' * 100, 0x21),
+    ('d.txt', b'deflate\n' * 100, 0x20),
+]
+solid = b''.join(data for _, data, _ in contents[:3])
+packed = [
+    lzma.compress(solid, format=lzma.FORMAT_RAW, filters=[{'id': lzma.FILTER_LZMA2, 'dict_size': 1 << 20}]),
+    lzma.compress(prog, format=lzma.FORMAT_RAW, filters=[{'id': lzma.FILTER_X86}, LZMA1(1 << 16)]),
+    bz2.compress(contents[4][1]),
+    zlib.compress(contents[5][1])[2:-4],
+]
+folders = [
+    ([(b'\x21', bytes([16]))], len(solid)),
+    ([(b'\x03\x03\x01\x03', b''), (b'\x03\x01\x01', b'\x5d' + struct.pack('<I', 1 << 16))], len(prog)),
+    ([(b'\x04\x02\x02', b'')], len(contents[4][1])),
+    ([(b'\x04\x01\x08', b'')], len(contents[5][1])),
+]
+entries = [('a', None, unix(0o40755)), ('empty', None, 0x20)] + contents
+header = b'\x01\x04' + streams(0, packed, folders, [[9, 27, 3], [len(prog)], [600], [800]]) + files(entries) + b'\x00'
+
+encoded = lzma.compress(header, format=lzma.FORMAT_RAW, filters=[LZMA1(1 << 16)])
+data = b''.join(packed)
+folders = [([(b'\x03\x01\x01', b'\x5d' + struct.pack('<I', 1 << 16))], len(header))]
+with open(sys.argv[1] + '/simple.7z', 'wb') as f:
+    f.write(archive(data + encoded, b'\x17' + streams(len(data), [encoded], folders)))
+
+secret = b'\x55' * 32
+folders = [([(b'\x06\xf1\x07\x01', b'\x00'), (b'\x03\x01\x01', b'\x5d' + struct.pack('<I', 1 << 16))], 16)]
+header = b'\x01\x04' + streams(0, [secret], folders) + files([('secret', secret, 0x20)]) + b'\x00'
+with open(sys.argv[1] + '/encrypted.7z', 'wb') as f:
+    f.write(archive(secret, header))
+PY
+
+# 7zip-*.7z aren't made here, but by 7-Zip itself, so the reader isn't only checked against the
+# encoder above: 7zip-bcj.7z (LZMA2 and x86 BCJ) and 7zip-delta.7z are from sevenz-rust's tests
+# (Apache-2.0), and 7zip-solid.7z (LZMA2, two files in one folder) from conda-forge's libarchive
+# feedstock's tests (BSD-3-Clause)
+
+# two gzip members, as pigz or log rotation make; the first with a comment and an extra field.
+# Then a commented tar.gz, where the comment is about the tar, which doesn't appear itself
+python3 - "${O}" <<'PY'
+import io, struct, sys, tarfile, zlib
+
+def member(data, name=None, comment=None, extra=b'', os=3):
+    flags = (0x04 if extra else 0) | (0x08 if name else 0) | (0x10 if comment else 0)
+    out = b'\x1f\x8b\x08' + bytes([flags]) + struct.pack('<I', 1500000000) + b'\x00' + bytes([os])
+    if extra:
+        out += struct.pack('<H', len(extra)) + extra
+    if name:
+        out += name + b'\0'
+    if comment:
+        out += comment + b'\0'
+    deflate = zlib.compressobj(9, zlib.DEFLATED, -15)
+    out += deflate.compress(data) + deflate.flush()
+    return out + struct.pack('<II', zlib.crc32(data), len(data))
+
+with open(sys.argv[1] + '/members.txt.gz', 'wb') as f:
+    f.write(member(b'first\n', name=b'members.txt', comment=b'rotated', extra=b'AP\x02\x00hi'))
+    f.write(member(b'second\n', os=11))
+
+tar = io.BytesIO()
+with tarfile.open(fileobj=tar, mode='w', format=tarfile.USTAR_FORMAT) as t:
+    i = tarfile.TarInfo('hello.txt')
+    i.size, i.mtime, i.mode = 6, 1500000000, 0o644
+    t.addfile(i, io.BytesIO(b'hello\n'))
+with open(sys.argv[1] + '/commented.tar.gz', 'wb') as f:
+    f.write(member(tar.getvalue(), name=b'commented.tar', comment=b'nightly build'))
+PY
+
+# zip extras: extended timestamps, Info-ZIP unix ids, NTFS times, a symlink and comments
+python3 - "${O}/extras.zip" <<'PY'
+import struct, sys, zipfile
+
+def info(name, mode, extra=b'', comment=b''):
+    i = zipfile.ZipInfo(name, (2017, 7, 14, 2, 40, 0))
+    i.create_system = 3
+    i.external_attr = mode << 16
+    i.extra = extra
+    i.comment = comment
+    return i
+
+timestamp = struct.pack('<HHBIII', 0x5455, 13, 7, 1500000000, 1500000001, 1500000002)
+unix = struct.pack('<HHBBIBI', 0x7875, 11, 1, 4, 1000, 4, 1001)
+filetime = (1500000000 + 11644473600) * 10000000 + 1234
+ntfs = struct.pack('<HHIHHQQQ', 0x000a, 32, 0, 1, 24, filetime, filetime, filetime)
+
+with zipfile.ZipFile(sys.argv[1], 'w') as zf:
+    zf.comment = b'the archive'
+    zf.writestr(info('plain', 0o100644, timestamp + unix, b'a file'), b'123456789')
+    zf.writestr(info('ntfs', 0o100644, ntfs), b'123456789')
+    zf.writestr(info('link', 0o120777, unix), b'plain')
+PY
+
+# a zip in a zip, to check what's buffered to look inside is only counted once
+python3 - "${O}/nested.zip" <<'PY'
+import io, sys, zipfile
+
+def stored(entries):
+    out = io.BytesIO()
+    with zipfile.ZipFile(out, 'w') as zf:
+        for name, data in entries:
+            zf.writestr(zipfile.ZipInfo(name, (2017, 7, 14, 2, 40, 0)), data)
+    return out.getvalue()
+
+with open(sys.argv[1], 'wb') as f:
+    f.write(stored([('inner.zip', stored([('data', b'x' * 1000)]))]))
+PY
+
+# ar archives: GNU style, with a symbol table and long names, and BSD style
+python3 - "${O}" <<'PY'
+import sys
+
+def member(name, data, mtime=1500000000, uid=1000, gid=1001, mode=0o100640):
+    header = b'%-16s%-12d%-6d%-6d%-8o%-10d`\n' % (name, mtime, uid, gid, mode, len(data))
+    return header + data + b'\n' * (len(data) % 2)
+
+long_name = b'a_rather_long_object_name.o'
+with open(sys.argv[1] + '/gnu.a', 'wb') as f:
+    f.write(b'!<arch>\n')
+    f.write(member(b'/', b'\0\0\0\0', 0, 0, 0, 0))
+    f.write(member(b'//', long_name + b'/\n', 0, 0, 0, 0))
+    f.write(member(b'/0', b'123456789'))
+    f.write(member(b'short.o/', b'hello\n'))
+
+with open(sys.argv[1] + '/bsd.a', 'wb') as f:
+    f.write(b'!<arch>\n')
+    # like BSD ar, pad the name with NULs, so the parity of the member size is the data's
+    padded = long_name + b'\0' * (-len(long_name) % 4)
+    f.write(member(b'#1/%d' % len(padded), padded + b'123456789'))
+    f.write(member(b'short.o', b'hello\n'))
+PY
+
+# pax: a global header, xattrs as GNU tar and libarchive write them, and sub-second times
+python3 - "${O}" <<'PY'
+import io, sys, tarfile
+
+with tarfile.open(sys.argv[1] + '/pax.tar', 'w', format=tarfile.PAX_FORMAT,
+                  pax_headers={'comment': 'global'}) as tf:
+    info = tarfile.TarInfo('xattrs')
+    info.size = 9
+    info.mtime = 1500000000
+    info.pax_headers = {
+        'mtime': '1500000000.123456789',
+        'atime': '1500000001.5',
+        'SCHILY.xattr.user.schily': 'one',
+        'LIBARCHIVE.xattr.user.lib%20archive': 'dHdv',
+    }
+    tf.addfile(info, io.BytesIO(b'123456789'))
+    # the global header has already been seen; an mtime we can't read leaves the header's
+    later = tarfile.TarInfo('later')
+    later.mtime = 1500000002
+    later.pax_headers = {'mtime': 'soon'}
+    tf.addfile(later)
+
+# globals.tar: a global header before a nested tar, and another before a file to --exclude
+def records(pairs):
+    out = b''
+    for key, value in pairs.items():
+        body = b' %s=%s\n' % (key.encode(), value.encode())
+        size = len(body) + 1
+        while len(b'%d' % size) + len(body) != size:
+            size += 1
+        out += b'%d' % size + body
+    return out
+
+def member(tf, name, data, type=tarfile.REGTYPE):
+    info = tarfile.TarInfo(name)
+    info.type = type
+    info.size = len(data)
+    info.mtime = 1500000000
+    tf.addfile(info, io.BytesIO(data))
+
+inner = io.BytesIO()
+with tarfile.open(fileobj=inner, mode='w', format=tarfile.USTAR_FORMAT) as tf:
+    member(tf, 'inside', b'nested\n')
+
+with tarfile.open(sys.argv[1] + '/globals.tar', 'w', format=tarfile.USTAR_FORMAT) as tf:
+    member(tf, 'pax_global_header', records({'comment': 'first'}), tarfile.XGLTYPE)
+    member(tf, 'inner.tar', inner.getvalue())
+    member(tf, 'pax_global_header', records({'comment': 'second'}), tarfile.XGLTYPE)
+    member(tf, 'skipped', b'skipped\n')
+    member(tf, 'kept', b'kept\n')
+PY
+
+# sparse files, in old GNU format and each of the pax versions
+T=$(mktemp -d)
+(
+    cd "$T"
+    python3 -c "
+f = open('disk.img', 'wb')
+f.truncate(64 * 1024)
+f.seek(8192)
+f.write(b'123456789')
+f.seek(40960)
+f.write(b'hello')"
+    tar --sparse --format=gnu --mtime=@1500000000 --owner=0 --group=0 \
+        -cf "${O}/sparse-gnu.tar" disk.img
+    for v in 0.0 0.1 1.0; do
+        tar --sparse --format=pax --sparse-version=$v --pax-option=delete=atime,delete=ctime \
+            --mtime=@1500000000 --owner=0 --group=0 -cf "${O}/sparse-$v.tar" disk.img
+    done
+)
+rm -rf "$T"
+
+# names which aren't UTF-8: Latin-1 in a tar; CP437, and a Unicode Path field, in a zip
+python3 - "${O}" <<'PY'
+import io, struct, sys, tarfile, zipfile, zlib
+
+with tarfile.open(sys.argv[1] + '/latin1.tar', 'w', format=tarfile.GNU_FORMAT,
+                  encoding='latin-1') as tf:
+    info = tarfile.TarInfo('caf\xe9')
+    info.size = 9
+    info.mtime = 1500000000
+    tf.addfile(info, io.BytesIO(b'123456789'))
+
+# zipfile would set the UTF-8 flag for any non-ASCII name, so patch the names in afterwards
+unicode_path = 'new-\xe9'.encode()
+extra = struct.pack('<HHBI', 0x7075, 5 + len(unicode_path), 1, zlib.crc32(b'old\x82'))
+out = io.BytesIO()
+with zipfile.ZipFile(out, 'w') as zf:
+    zf.writestr(zipfile.ZipInfo('cafX', (2017, 7, 14, 2, 40, 0)), b'123456789')
+    info = zipfile.ZipInfo('oldX', (2017, 7, 14, 2, 40, 0))
+    info.extra = extra + unicode_path
+    zf.writestr(info, b'123456789')
+data = out.getvalue().replace(b'cafX', b'caf\x82').replace(b'oldX', b'old\x82')
+with open(sys.argv[1] + '/cp437.zip', 'wb') as f:
+    f.write(data)
+PY
@@ -0,0 +1,357 @@
+use std::io;
+
+use crate::tee::read_all;
+use crate::tee::skip;
+
+pub const TRAILER: &[u8] = b"TRAILER!!!";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// "new ascii", `070701`: hex fields, four-byte alignment
+    Newc,
+    /// "new crc", `070702`: as `Newc`, with a (useless) checksum field populated
+    Crc,
+    /// "old ascii", `070707`: octal fields, no alignment
+    Odc,
+    /// "old binary", 16-bit fields, two-byte alignment, in the writer's byte order
+    BinaryLittle,
+    BinaryBig,
+}
+
+impl Format {
+    pub fn identify(header: &[u8]) -> Option<Format> {
+        if header.len() >= 6 && b"07070" == &header[..5] {
+            return match header[5] {
+                b'1' => Some(Format::Newc),
+                b'2' => Some(Format::Crc),
+                b'7' => Some(Format::Odc),
+                _ => None,
+            };
+        }
+
+        if header.len() < 26 {
+            return None;
+        }
+
+        // the binary format only has a two-byte magic, so check the name looks terminated, too
+        let (format, namesize) = match (header[0], header[1]) {
+            (0xc7, 0x71) => (
+                Format::BinaryLittle,
+                u16::from_le_bytes([header[20], header[21]]),
+            ),
+            (0x71, 0xc7) => (
+                Format::BinaryBig,
+                u16::from_be_bytes([header[20], header[21]]),
+            ),
+            _ => return None,
+        };
+
+        let name_end = 26 + namesize as usize;
+        if 0 == namesize || (name_end <= header.len() && 0 != header[name_end - 1]) {
+            return None;
+        }
+
+        Some(format)
+    }
+
+    fn header_len(self) -> usize {
+        match self {
+            Format::Newc | Format::Crc => 110,
+            Format::Odc => 76,
+            Format::BinaryLittle | Format::BinaryBig => 26,
+        }
+    }
+
+    fn alignment(self) -> u64 {
+        match self {
+            Format::Newc | Format::Crc => 4,
+            Format::Odc => 1,
+            Format::BinaryLittle | Format::BinaryBig => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub format: Format,
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    pub ino: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub mtime: u64,
+    pub rdev_major: u32,
+    pub rdev_minor: u32,
+    pub file_size: u64,
+    pub name: Vec<u8>,
+}
+
+impl Header {
+    pub fn is_trailer(&self) -> bool {
+        TRAILER == &self.name[..]
+    }
+
+    /// Bytes of padding which follow the file data.
+    pub fn data_padding(&self) -> u64 {
+        padding(self.file_size, self.format.alignment())
+    }
+}
+
+fn padding(len: u64, alignment: u64) -> u64 {
+    (alignment - len % alignment) % alignment
+}
+
+fn invalid<T: AsRef<str>>(msg: T) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.as_ref())
+}
+
+fn parse_radix(bytes: &[u8], radix: u32) -> io::Result<u64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| u64::from_str_radix(s, radix).ok())
+        .ok_or_else(|| invalid(format!("invalid cpio header field: {:?}", bytes)))
+}
+
+/// Old formats pack the device into sixteen(ish) bits, like an old `dev_t`.
+fn split_old_dev(dev: u64) -> (u32, u32) {
+    (((dev >> 8) & 0xff) as u32, (dev & 0xff) as u32)
+}
+
+/// Read the next header (and name) from the stream, or `None` if it is already exhausted.
+pub fn read_header<R: io::BufRead + ?Sized>(from: &mut R) -> io::Result<Option<Header>> {
+    if from.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    let mut buf = [0u8; 110];
+    let found = read_all(&mut *from, &mut buf[..6])?;
+    let format = Format::identify(&buf[..found])
+        .or_else(|| {
+            // the binary magic isn't enough to identify on its own; read the rest and retry
+            let rest = read_all(&mut *from, &mut buf[found..26]).ok()?;
+            Format::identify(&buf[..found + rest])
+                .filter(|f| Format::BinaryLittle == *f || Format::BinaryBig == *f)
+        })
+        .ok_or_else(|| invalid(format!("invalid cpio magic: {:?}", &buf[..6])))?;
+
+    let already = match format {
+        Format::BinaryLittle | Format::BinaryBig => 26,
+        _ => 6,
+    };
+
+    let len = format.header_len();
+    from.read_exact(&mut buf[already..len])?;
+    let buf = &buf[..len];
+
+    let (mut header, namesize) = match format {
+        Format::Newc | Format::Crc => {
+            let field = |i: usize| parse_radix(&buf[6 + i * 8..6 + (i + 1) * 8], 16);
+            (
+                Header {
+                    format,
+                    ino: field(0)?,
+                    mode: field(1)? as u32,
+                    uid: field(2)? as u32,
+                    gid: field(3)? as u32,
+                    nlink: field(4)? as u32,
+                    mtime: field(5)?,
+                    file_size: field(6)?,
+                    dev_major: field(7)? as u32,
+                    dev_minor: field(8)? as u32,
+                    rdev_major: field(9)? as u32,
+                    rdev_minor: field(10)? as u32,
+                    name: Vec::new(),
+                },
+                field(11)?,
+            )
+        }
+        Format::Odc => {
+            let field = |start: usize, len: usize| parse_radix(&buf[start..start + len], 8);
+            let (dev_major, dev_minor) = split_old_dev(field(6, 6)?);
+            let (rdev_major, rdev_minor) = split_old_dev(field(42, 6)?);
+            (
+                Header {
+                    format,
+                    dev_major,
+                    dev_minor,
+                    ino: field(12, 6)?,
+                    mode: field(18, 6)? as u32,
+                    uid: field(24, 6)? as u32,
+                    gid: field(30, 6)? as u32,
+                    nlink: field(36, 6)? as u32,
+                    rdev_major,
+                    rdev_minor,
+                    mtime: field(48, 11)?,
+                    file_size: field(65, 11)?,
+                    name: Vec::new(),
+                },
+                field(59, 6)?,
+            )
+        }
+        Format::BinaryLittle | Format::BinaryBig => {
+            let field = |i: usize| {
+                let pair = [buf[i * 2], buf[i * 2 + 1]];
+                u64::from(match format {
+                    Format::BinaryLittle => u16::from_le_bytes(pair),
+                    _ => u16::from_be_bytes(pair),
+                })
+            };
+            let (dev_major, dev_minor) = split_old_dev(field(1));
+            let (rdev_major, rdev_minor) = split_old_dev(field(7));
+            (
+                Header {
+                    format,
+                    dev_major,
+                    dev_minor,
+                    ino: field(2),
+                    mode: field(3) as u32,
+                    uid: field(4) as u32,
+                    gid: field(5) as u32,
+                    nlink: field(6) as u32,
+                    rdev_major,
+                    rdev_minor,
+                    // the 32-bit values are stored most-significant half first, regardless
+                    mtime: field(8) << 16 | field(9),
+                    file_size: field(11) << 16 | field(12),
+                    name: Vec::new(),
+                },
+                field(10),
+            )
+        }
+    };
+
+    if 0 == namesize || namesize > 64 * 1024 {
+        return Err(invalid(format!(
+            "unreasonable cpio name length: {}",
+            namesize
+        )));
+    }
+
+    let mut name = vec![0u8; namesize as usize];
+    from.read_exact(&mut name)?;
+    if Some(0) != name.pop() {
+        return Err(invalid("cpio name wasn't terminated"));
+    }
+    header.name = name;
+
+    let name_padding = padding(len as u64 + namesize, format.alignment());
+    skip(from, name_padding)?;
+
+    Ok(Some(header))
+}
+
+/// Archives are padded out to a block size with zeros after the trailer,
+/// and concatenated archives (e.g. an `initramfs`) are aligned likewise.
+pub fn skip_zeros<R: io::BufRead + ?Sized>(from: &mut R) -> io::Result<()> {
+    loop {
+        let (zeros, available) = {
+            let buf = from.fill_buf()?;
+            (buf.iter().take_while(|&&b| 0 == b).count(), buf.len())
+        };
+
+        from.consume(zeros);
+
+        if zeros != available || 0 == available {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn newc() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"070701");
+        for field in &[
+            0x1234u32, 0o100644, 1000, 1001, 2, 1500000000, 5, 8, 1, 0, 0, 4, 0,
+        ] {
+            data.extend_from_slice(format!("{:08x}", field).as_bytes());
+        }
+        data.extend_from_slice(b"foo\0");
+        // 110 + 4 => 114, padded to 116
+        data.extend_from_slice(b"\0\0");
+        data.extend_from_slice(b"hello\0\0\0");
+
+        let mut from = io::Cursor::new(data);
+        let header = read_header(&mut from).unwrap().unwrap();
+        assert_eq!(Format::Newc, header.format);
+        assert_eq!(0x1234, header.ino);
+        assert_eq!(0o100644, header.mode);
+        assert_eq!(1001, header.gid);
+        assert_eq!(2, header.nlink);
+        assert_eq!(1500000000, header.mtime);
+        assert_eq!(5, header.file_size);
+        assert_eq!((8, 1), (header.dev_major, header.dev_minor));
+        assert_eq!(b"foo", &header.name[..]);
+        assert_eq!(3, header.data_padding());
+        assert_eq!(116, from.position());
+    }
+
+    #[test]
+    fn odc() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"070707");
+        data.extend_from_slice(b"000401000007040755000000000000000002000000");
+        data.extend_from_slice(b"1313202740000000300000000000");
+        data.extend_from_slice(b"ab\0");
+
+        let mut from = io::Cursor::new(data);
+        let header = read_header(&mut from).unwrap().unwrap();
+        assert_eq!(Format::Odc, header.format);
+        assert_eq!((1, 1), (header.dev_major, header.dev_minor));
+        assert_eq!(7, header.ino);
+        assert_eq!(0o40755, header.mode);
+        assert_eq!(2, header.nlink);
+        assert_eq!(0o13132027400, header.mtime);
+        assert_eq!(b"ab", &header.name[..]);
+        assert_eq!(0, header.file_size);
+        assert!(read_header(&mut from).unwrap().is_none());
+    }
+
+    #[test]
+    fn binary_little() {
+        let mut data = Vec::new();
+        for field in &[
+            0o070707u16,
+            0,
+            3,
+            0o100600,
+            0,
+            0,
+            1,
+            0,
+            0x5965,
+            0x5e00,
+            11,
+            0,
+            1,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(b"TRAILER!!!\0\0");
+        data.extend_from_slice(b"x\0");
+
+        let mut from = io::Cursor::new(data);
+        let header = read_header(&mut from).unwrap().unwrap();
+        assert_eq!(Format::BinaryLittle, header.format);
+        assert_eq!(0x5965_5e00, header.mtime);
+        assert_eq!(1, header.file_size);
+        assert!(header.is_trailer());
+        assert_eq!(1, header.data_padding());
+        assert_eq!(38, from.position());
+    }
+
+    #[test]
+    fn zeros() {
+        let mut from = io::Cursor::new(vec![0u8, 0, 0, 7, 0]);
+        skip_zeros(&mut from).unwrap();
+        assert_eq!(3, from.position());
+    }
+}
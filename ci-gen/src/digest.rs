@@ -0,0 +1,170 @@
+use std::io;
+
+use crc::Hasher32;
+use sha2::Digest;
+
+/// `--hash`: what we can hash the content with, and what we call each in the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Castagnoli, like `ci-dump` shows.
+    Crc32c,
+    Sha1,
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        Some(match name {
+            "crc32c" => Algorithm::Crc32c,
+            "sha1" => Algorithm::Sha1,
+            "sha256" => Algorithm::Sha256,
+            "sha512" => Algorithm::Sha512,
+            "blake3" => Algorithm::Blake3,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Crc32c => "crc32c",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Crc32c => {
+                Hasher::Crc32c(Box::new(crc::crc32::Digest::new(crc::crc32::CASTAGNOLI)))
+            }
+            Algorithm::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(sha2::Sha512::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+enum Hasher {
+    /// carries its whole table around
+    Crc32c(Box<crc::crc32::Digest>),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    /// keeps a stack of chunk states inline
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32c(h) => h.write(data),
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    /// The crc is big-endian, so it reads the same as its hex.
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Crc32c(h) => h.sum32().to_be_bytes().to_vec(),
+            Hasher::Sha1(h) => h.finalize().to_vec(),
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Sha512(h) => h.finalize().to_vec(),
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Hashes everything read through it.
+pub struct Hashing<R> {
+    inner: R,
+    hashers: Vec<(Algorithm, Hasher)>,
+}
+
+impl<R: io::Read> Hashing<R> {
+    pub fn new(algorithms: &[Algorithm], inner: R) -> Hashing<R> {
+        Hashing {
+            inner,
+            hashers: algorithms.iter().map(|&a| (a, a.hasher())).collect(),
+        }
+    }
+
+    /// (name, digest), in the order they were asked for.
+    pub fn finish(self) -> Vec<(String, Vec<u8>)> {
+        self.hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| (algorithm.name().to_string(), hasher.finish()))
+            .collect()
+    }
+}
+
+impl<R: io::Read> io::Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let found = self.inner.read(buf)?;
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(&buf[..found]);
+        }
+        Ok(found)
+    }
+}
+
+/// Read all of `from`, just to hash it.
+pub fn digest<R: io::Read>(
+    algorithms: &[Algorithm],
+    from: R,
+) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut hashing = Hashing::new(algorithms, from);
+    io::copy(&mut hashing, &mut io::sink())?;
+    Ok(hashing.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn known() {
+        let all = [Algorithm::Crc32c, Algorithm::Sha1, Algorithm::Sha256];
+        let digests = digest(&all, &b"123456789"[..]).unwrap();
+        let digests: Vec<(&str, String)> = digests
+            .iter()
+            .map(|(name, value)| (name.as_str(), hex(value)))
+            .collect();
+        assert_eq!(
+            vec![
+                ("crc32c", "e3069283".to_string()),
+                (
+                    "sha1",
+                    "f7c3bc1d808e04732adf679965ccc34ca7ae3441".to_string()
+                ),
+                (
+                    "sha256",
+                    "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225".to_string()
+                ),
+            ],
+            digests
+        );
+
+        let empty = digest(&[Algorithm::Blake3], io::empty()).unwrap();
+        assert_eq!(
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+            hex(&empty[0].1)
+        );
+
+        assert_eq!(Some(Algorithm::Sha512), Algorithm::from_name("sha512"));
+        assert_eq!(None, Algorithm::from_name("md5"));
+    }
+}
@@ -10,6 +10,14 @@ pub enum ErrorKind {
     /// format is (probably) legal, but we refuse to support its feature
     #[error("unsupported feature: {0}")]
     UnsupportedFeature(String),
+
+    /// we could carry on, but `Limits` say we shouldn't
+    #[error("limit exceeded: {0}")]
+    Limit(String),
+
+    /// `Limits::entries`: nothing's wrong with the container, we just stop
+    #[error("limit exceeded: {0}")]
+    EntryLimit(String),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -38,10 +46,26 @@ pub fn classify_format_error_result<T>(res: &Result<T>) -> Option<FormatErrorTyp
     }
 }
 
+pub fn is_limit_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.root_cause().downcast_ref::<ErrorKind>(),
+        Some(ErrorKind::Limit(_))
+    )
+}
+
+pub fn is_entry_limit_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.root_cause().downcast_ref::<ErrorKind>(),
+        Some(ErrorKind::EntryLimit(_))
+    )
+}
+
 fn is_format_error(e: &ErrorKind) -> Option<FormatErrorType> {
     match e {
         ErrorKind::Rewind => Some(FormatErrorType::Rewind),
-        ErrorKind::UnsupportedFeature(_) => Some(FormatErrorType::Other),
+        ErrorKind::UnsupportedFeature(_) | ErrorKind::Limit(_) => Some(FormatErrorType::Other),
+        // so it isn't rolled back, which would emit everything again
+        ErrorKind::EntryLimit(_) => None,
     }
 }
 
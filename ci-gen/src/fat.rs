@@ -0,0 +1,657 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+const END_OF_CHAIN: u32 = u32::MAX;
+
+const ATTR_VOLUME_LABEL: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Variant {
+    Fat12,
+    Fat16,
+    Fat32,
+    ExFat,
+}
+
+/// A DOS date and time, as found in FAT and zip, in local time unless `offset` says otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timestamp {
+    pub date: u16,
+    pub time: u16,
+    /// Extra precision, in units of ten milliseconds; up to two seconds.
+    pub centis: u8,
+    /// Minutes east of UTC, which only exFAT records.
+    pub offset: Option<i16>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Entry {
+    pub path: String,
+    pub is_dir: bool,
+    pub len: u64,
+    pub mtime: Timestamp,
+    pub atime: Timestamp,
+    pub btime: Timestamp,
+    first_cluster: u32,
+    /// exFAT can store files as a contiguous run, ignoring the FAT.
+    contiguous: bool,
+    /// exFAT can have allocated, but unwritten, space at the end of a file, which reads as zeros.
+    valid_len: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Root {
+    /// FAT12 and FAT16 have a fixed-size region for the root directory.
+    Fixed { offset: u64, len: u64 },
+    /// exFAT directories record their size, and can be contiguous, like files.
+    Cluster {
+        first: u32,
+        contiguous: bool,
+        len: u64,
+    },
+}
+
+pub struct Fat<R> {
+    inner: R,
+    variant: Variant,
+    cluster_size: u64,
+    /// Where cluster 2, the first one, starts.
+    data_start: u64,
+    /// Every entry in the (first) allocation table, with the end markers normalised.
+    table: Vec<u32>,
+    root: Root,
+}
+
+fn invalid<T: AsRef<str>>(msg: T) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.as_ref())
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    u64::from(le32(bytes)) | u64::from(le32(&bytes[4..])) << 32
+}
+
+/// Is this the boot sector of a FAT or exFAT filesystem?
+pub fn is_fat(header: &[u8]) -> bool {
+    if header.len() < 512 {
+        return false;
+    }
+
+    if b"EXFAT   " == &header[3..11] {
+        return true;
+    }
+
+    // the type label is only informational, but everything writes it
+    0x55 == header[510]
+        && 0xaa == header[511]
+        && [512, 1024, 2048, 4096].contains(&le16(&header[11..]))
+        && (b"FAT" == &header[54..57] || b"FAT32   " == &header[82..90])
+}
+
+fn utf16_name(units: &[u16]) -> String {
+    let end = units.iter().position(|&u| 0 == u).unwrap_or(units.len());
+    String::from_utf16_lossy(&units[..end])
+}
+
+/// An 8.3 name, with the NT "lowercase" flags applied.
+fn short_name(entry: &[u8]) -> String {
+    let mut raw = entry[..11].to_vec();
+    // a real leading 0xe5 is stored as 0x05, as 0xe5 means "deleted"
+    if 0x05 == raw[0] {
+        raw[0] = 0xe5;
+    }
+
+    let part = |bytes: &[u8], lower: bool| {
+        let text = String::from_utf8_lossy(bytes).trim_end().to_string();
+        if lower {
+            text.to_ascii_lowercase()
+        } else {
+            text
+        }
+    };
+
+    let base = part(&raw[..8], 0 != entry[12] & 0x08);
+    let ext = part(&raw[8..11], 0 != entry[12] & 0x10);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+fn short_name_checksum(entry: &[u8]) -> u8 {
+    entry[..11]
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+impl<R: Read + Seek> Fat<R> {
+    pub fn open(mut inner: R) -> io::Result<Fat<R>> {
+        let mut boot = [0u8; 512];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut boot)?;
+
+        if b"EXFAT   " == &boot[3..11] {
+            return Fat::open_exfat(inner, &boot);
+        }
+
+        let bytes_per_sector = u64::from(le16(&boot[11..]));
+        let sectors_per_cluster = u64::from(boot[13]);
+        let reserved = u64::from(le16(&boot[14..]));
+        let fats = u64::from(boot[16]);
+        let root_entries = u64::from(le16(&boot[17..]));
+        let total_sectors = match le16(&boot[19..]) {
+            0 => u64::from(le32(&boot[32..])),
+            small => u64::from(small),
+        };
+        let fat_sectors = match le16(&boot[22..]) {
+            0 => u64::from(le32(&boot[36..])),
+            small => u64::from(small),
+        };
+
+        if 0 == sectors_per_cluster || 0 == fats || 0 == fat_sectors {
+            return Err(invalid("invalid fat boot sector"));
+        }
+
+        let root_sectors = (root_entries * 32).div_ceil(bytes_per_sector);
+        let root_start = reserved + fats * fat_sectors;
+        let data_start = root_start + root_sectors;
+        let clusters = total_sectors.saturating_sub(data_start) / sectors_per_cluster;
+
+        // the variant is determined by the cluster count, and nothing else
+        let variant = if clusters < 4085 {
+            Variant::Fat12
+        } else if clusters < 65525 {
+            Variant::Fat16
+        } else {
+            Variant::Fat32
+        };
+
+        let root = match variant {
+            Variant::Fat32 => Root::Cluster {
+                first: le32(&boot[44..]),
+                contiguous: false,
+                len: 0,
+            },
+            _ => Root::Fixed {
+                offset: root_start * bytes_per_sector,
+                len: root_entries * 32,
+            },
+        };
+
+        let mut fs = Fat {
+            inner,
+            variant,
+            cluster_size: sectors_per_cluster * bytes_per_sector,
+            data_start: data_start * bytes_per_sector,
+            table: Vec::new(),
+            root,
+        };
+
+        fs.load_table(reserved * bytes_per_sector, clusters + 2)?;
+        Ok(fs)
+    }
+
+    fn open_exfat(inner: R, boot: &[u8]) -> io::Result<Fat<R>> {
+        let bytes_per_sector_shift = boot[108];
+        let sectors_per_cluster_shift = boot[109];
+        if !(9..=12).contains(&bytes_per_sector_shift)
+            || bytes_per_sector_shift + sectors_per_cluster_shift > 25
+        {
+            return Err(invalid("invalid exfat boot sector"));
+        }
+
+        let bytes_per_sector = 1u64 << bytes_per_sector_shift;
+        let mut fs = Fat {
+            inner,
+            variant: Variant::ExFat,
+            cluster_size: bytes_per_sector << sectors_per_cluster_shift,
+            data_start: u64::from(le32(&boot[88..])) * bytes_per_sector,
+            table: Vec::new(),
+            root: Root::Cluster {
+                first: le32(&boot[96..]),
+                contiguous: false,
+                len: 0,
+            },
+        };
+
+        let fat_offset = u64::from(le32(&boot[80..])) * bytes_per_sector;
+        fs.load_table(fat_offset, u64::from(le32(&boot[92..])) + 2)?;
+        Ok(fs)
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    fn load_table(&mut self, offset: u64, entries: u64) -> io::Result<()> {
+        if entries > 64 * 1024 * 1024 {
+            return Err(invalid(format!("unreasonable cluster count: {}", entries)));
+        }
+
+        let bytes = match self.variant {
+            Variant::Fat12 => (entries * 3).div_ceil(2),
+            Variant::Fat16 => entries * 2,
+            Variant::Fat32 | Variant::ExFat => entries * 4,
+        };
+
+        let mut raw = vec![0u8; bytes as usize];
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut raw)?;
+
+        let (mask, end) = match self.variant {
+            Variant::Fat12 => (0xfff, 0xff7),
+            Variant::Fat16 => (0xffff, 0xfff7),
+            // the top four bits are reserved
+            Variant::Fat32 => (0x0fff_ffff, 0x0fff_fff7),
+            Variant::ExFat => (0xffff_ffff, 0xffff_fff7),
+        };
+
+        self.table = (0..entries as usize)
+            .map(|i| {
+                let value = match self.variant {
+                    Variant::Fat12 => {
+                        let pair = le16(&raw[i * 3 / 2..]);
+                        u32::from(if 0 == i % 2 { pair & 0xfff } else { pair >> 4 })
+                    }
+                    Variant::Fat16 => u32::from(le16(&raw[i * 2..])),
+                    Variant::Fat32 | Variant::ExFat => le32(&raw[i * 4..]),
+                } & mask;
+
+                // bad clusters, and the end of the chain
+                if value >= end {
+                    END_OF_CHAIN
+                } else {
+                    value
+                }
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    fn clusters(&self, first: u32, contiguous: bool, len: u64) -> io::Result<Vec<u32>> {
+        let needed = len.div_ceil(self.cluster_size);
+        if needed > self.table.len() as u64 {
+            return Err(invalid(format!("file larger than filesystem: {}", len)));
+        }
+
+        if contiguous {
+            if 0 == needed {
+                return Ok(Vec::new());
+            }
+            if first < 2 || u64::from(first) + needed > self.table.len() as u64 {
+                return Err(invalid(format!(
+                    "invalid cluster run: {}+{}",
+                    first, needed
+                )));
+            }
+            return Ok((first..first + needed as u32).collect());
+        }
+
+        let mut ret = Vec::new();
+        let mut current = first;
+        while END_OF_CHAIN != current {
+            if current < 2 || current as usize >= self.table.len() {
+                return Err(invalid(format!("invalid cluster: {}", current)));
+            }
+
+            // a corrupt table can contain loops
+            if ret.len() >= self.table.len() {
+                return Err(invalid("cluster chain loops"));
+            }
+
+            ret.push(current);
+            current = self.table[current as usize];
+        }
+
+        Ok(ret)
+    }
+
+    fn read_clusters(&mut self, clusters: &[u32], limit: u64) -> io::Result<Vec<u8>> {
+        let mut ret = Vec::new();
+        let mut reader = Clusters::new(self, clusters.to_vec(), limit, limit);
+        reader.read_to_end(&mut ret)?;
+        Ok(ret)
+    }
+
+    fn read_directory(&mut self, dir: &Root) -> io::Result<Vec<u8>> {
+        // FAT directories don't know their own size; guard against following a huge chain
+        let limit = 64 * 1024 * 1024;
+        match *dir {
+            Root::Fixed { offset, len } => {
+                let mut data = vec![0u8; len as usize];
+                self.inner.seek(SeekFrom::Start(offset))?;
+                self.inner.read_exact(&mut data)?;
+                Ok(data)
+            }
+            Root::Cluster {
+                first,
+                contiguous,
+                len,
+            } => {
+                let clusters = self.clusters(first, contiguous, len)?;
+                let limit = if contiguous { len.min(limit) } else { limit };
+                self.read_clusters(&clusters, limit)
+            }
+        }
+    }
+
+    /// Every file and directory, parents before their children.
+    pub fn entries(&mut self) -> io::Result<Vec<Entry>> {
+        let mut ret = Vec::new();
+        let mut seen = HashSet::new();
+        let mut todo = vec![(String::new(), self.root)];
+
+        while let Some((prefix, dir)) = todo.pop() {
+            if let Root::Cluster { first, .. } = dir {
+                if !seen.insert(first) {
+                    continue;
+                }
+            }
+
+            let data = self.read_directory(&dir)?;
+            let children = match self.variant {
+                Variant::ExFat => exfat_entries(&data),
+                _ => fat_entries(&data),
+            };
+
+            for mut child in children {
+                child.path = format!("{}{}", prefix, child.path);
+                // empty directories can have no clusters at all
+                if child.is_dir && 0 != child.first_cluster {
+                    todo.push((
+                        format!("{}/", child.path),
+                        Root::Cluster {
+                            first: child.first_cluster,
+                            contiguous: child.contiguous,
+                            len: child.valid_len,
+                        },
+                    ));
+                }
+                ret.push(child);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn open_file<'f>(&'f mut self, entry: &Entry) -> io::Result<impl Read + 'f> {
+        let clusters = if 0 == entry.first_cluster {
+            Vec::new()
+        } else {
+            self.clusters(entry.first_cluster, entry.contiguous, entry.len)?
+        };
+
+        Ok(Clusters::new(self, clusters, entry.valid_len, entry.len))
+    }
+}
+
+fn fat_entries(data: &[u8]) -> Vec<Entry> {
+    let mut ret = Vec::new();
+    // long name parts, in the order they're stored, which is backwards; and the checksum
+    let mut long: Vec<&[u8]> = Vec::new();
+    let mut long_checksum = 0;
+
+    for raw in data.chunks_exact(32) {
+        match raw[0] {
+            0x00 => break,
+            0xe5 => {
+                long.clear();
+                continue;
+            }
+            _ => (),
+        }
+
+        let attributes = raw[11];
+        if ATTR_LONG_NAME == attributes & 0x3f {
+            if 0 != raw[0] & 0x40 {
+                long.clear();
+                long_checksum = raw[13];
+            }
+            long.push(raw);
+            continue;
+        }
+
+        if 0 != attributes & ATTR_VOLUME_LABEL || b'.' == raw[0] {
+            long.clear();
+            continue;
+        }
+
+        let name = if !long.is_empty() && long_checksum == short_name_checksum(raw) {
+            let units: Vec<u16> = long
+                .iter()
+                .rev()
+                .flat_map(|part| {
+                    [&part[1..11], &part[14..26], &part[28..32]]
+                        .concat()
+                        .chunks(2)
+                        .map(le16)
+                        .collect::<Vec<u16>>()
+                })
+                .collect();
+            utf16_name(&units)
+        } else {
+            short_name(raw)
+        };
+        long.clear();
+
+        let is_dir = 0 != attributes & ATTR_DIRECTORY;
+        let len = if is_dir {
+            0
+        } else {
+            u64::from(le32(&raw[28..]))
+        };
+
+        ret.push(Entry {
+            path: name,
+            is_dir,
+            len,
+            btime: Timestamp {
+                date: le16(&raw[16..]),
+                time: le16(&raw[14..]),
+                centis: raw[13],
+                offset: None,
+            },
+            atime: Timestamp {
+                date: le16(&raw[18..]),
+                ..Timestamp::default()
+            },
+            mtime: Timestamp {
+                date: le16(&raw[24..]),
+                time: le16(&raw[22..]),
+                ..Timestamp::default()
+            },
+            first_cluster: u32::from(le16(&raw[20..])) << 16 | u32::from(le16(&raw[26..])),
+            contiguous: false,
+            valid_len: len,
+        });
+    }
+
+    ret
+}
+
+fn exfat_offset(val: u8) -> Option<i16> {
+    if 0 == val & 0x80 {
+        return None;
+    }
+    // seven-bit two's complement, in fifteen minute intervals
+    Some(i16::from(((val << 1) as i8) >> 1) * 15)
+}
+
+fn exfat_entries(data: &[u8]) -> Vec<Entry> {
+    let mut ret = Vec::new();
+    let raws: Vec<&[u8]> = data.chunks_exact(32).collect();
+
+    let mut i = 0;
+    while i < raws.len() {
+        let raw = raws[i];
+        i += 1;
+
+        match raw[0] {
+            0x00 => break,
+            0x85 => (),
+            // bitmaps, labels, deleted files, ...
+            _ => continue,
+        }
+
+        let secondaries = raw[1] as usize;
+        let set = match raws.get(i..i + secondaries) {
+            Some(set) => set,
+            None => break,
+        };
+        i += secondaries;
+
+        let stream = match set.first() {
+            Some(stream) if 0xc0 == stream[0] => stream,
+            _ => continue,
+        };
+
+        let units: Vec<u16> = set[1..]
+            .iter()
+            .filter(|name| 0xc1 == name[0])
+            .flat_map(|name| name[2..32].chunks(2).map(le16).collect::<Vec<u16>>())
+            .take(stream[3] as usize)
+            .collect();
+
+        let stamp = |at: usize, centis: Option<usize>, offset: usize| Timestamp {
+            date: le16(&raw[at + 2..]),
+            time: le16(&raw[at..]),
+            centis: centis.map_or(0, |c| raw[c]),
+            offset: exfat_offset(raw[offset]),
+        };
+
+        let is_dir = 0 != le16(&raw[4..]) & u16::from(ATTR_DIRECTORY);
+        let len = le64(&stream[24..]);
+
+        ret.push(Entry {
+            path: utf16_name(&units),
+            is_dir,
+            len: if is_dir { 0 } else { len },
+            btime: stamp(8, Some(20), 22),
+            mtime: stamp(12, Some(21), 23),
+            atime: stamp(16, None, 24),
+            first_cluster: le32(&stream[20..]),
+            contiguous: 0 != stream[1] & 0x02,
+            valid_len: le64(&stream[8..]).min(len),
+        });
+    }
+
+    ret
+}
+
+struct Clusters<'f, R> {
+    fs: &'f mut Fat<R>,
+    clusters: Vec<u32>,
+    next: usize,
+    /// Left in the current cluster.
+    remaining: u64,
+    /// Left of the real data; then zeros until `len`.
+    valid: u64,
+    len: u64,
+}
+
+impl<'f, R> Clusters<'f, R> {
+    fn new(fs: &'f mut Fat<R>, clusters: Vec<u32>, valid: u64, len: u64) -> Self {
+        Clusters {
+            fs,
+            clusters,
+            next: 0,
+            remaining: 0,
+            valid,
+            len,
+        }
+    }
+}
+
+impl<'f, R: Read + Seek> Read for Clusters<'f, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if 0 == self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if 0 == self.valid {
+            let len = (buf.len() as u64).min(self.len) as usize;
+            buf[..len].iter_mut().for_each(|b| *b = 0);
+            self.len -= len as u64;
+            return Ok(len);
+        }
+
+        if 0 == self.remaining {
+            let cluster = match self.clusters.get(self.next) {
+                Some(&cluster) => cluster,
+                None => return Ok(0),
+            };
+            self.next += 1;
+            self.fs.inner.seek(SeekFrom::Start(
+                self.fs.data_start + u64::from(cluster - 2) * self.fs.cluster_size,
+            ))?;
+            self.remaining = self.fs.cluster_size;
+        }
+
+        let wanted = (buf.len() as u64).min(self.remaining).min(self.valid) as usize;
+        let found = self.fs.inner.read(&mut buf[..wanted])?;
+        if 0 == found {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining -= found as u64;
+        self.valid -= found as u64;
+        self.len -= found as u64;
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let mut entry = [0u8; 32];
+        entry[..11].copy_from_slice(b"README  TXT");
+        assert_eq!("README.TXT", short_name(&entry));
+        entry[12] = 0x08;
+        assert_eq!("readme.TXT", short_name(&entry));
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(0x53, short_name_checksum(b"FOO     BAR"));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(None, exfat_offset(0x04));
+        assert_eq!(Some(60), exfat_offset(0x84));
+        assert_eq!(Some(-300), exfat_offset(0xec));
+    }
+
+    #[test]
+    fn contiguous_runs() {
+        let fat = Fat {
+            inner: io::empty(),
+            variant: Variant::ExFat,
+            cluster_size: 512,
+            data_start: 0,
+            table: vec![0; 8],
+            root: Root::Fixed { offset: 0, len: 0 },
+        };
+        assert_eq!(vec![3, 4], fat.clusters(3, true, 1000).unwrap());
+        assert!(fat.clusters(0, true, 512).is_err());
+        assert!(fat.clusters(7, true, 1024).is_err());
+        assert!(fat.clusters(u32::MAX, true, 512).is_err());
+    }
+}
@@ -1,5 +1,11 @@
 use std::fmt;
 
+use crate::cpio;
+use crate::fat;
+use crate::rpm;
+use crate::sevenz;
+use crate::squashfs;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum FileType {
     GZip,
@@ -7,9 +13,17 @@ pub enum FileType {
     Tar,
     BZip2,
     Xz,
+    Zstd,
+    Cpio,
+    Rpm,
     Deb,
+    Ar,
     DiskImage,
     Ext4,
+    Fat,
+    Iso9660,
+    SquashFs,
+    SevenZip,
     Other,
 }
 
@@ -56,6 +70,7 @@ fn is_probably_tar(header: &[u8]) -> bool {
     false
 }
 
+const AR_MAGIC: &[u8] = b"!<arch>\n";
 const DEB_PREFIX: &[u8] = b"!<arch>\ndebian-binary ";
 
 impl FileType {
@@ -81,6 +96,9 @@ impl FileType {
             && header[0..DEB_PREFIX.len()] == DEB_PREFIX[..]
             && header[66..70] == b"`\n2."[..] {
             FileType::Deb
+        } else if header.len() >= AR_MAGIC.len()
+            && header[..AR_MAGIC.len()] == AR_MAGIC[..] {
+            FileType::Ar
         } else if header.len() > 40
             && b'B' == header[0] && b'Z' == header[1]
             && b'h' == header[2] // [3]: compression level
@@ -93,14 +111,45 @@ impl FileType {
             && b'z' == header[2] && b'X' == header[3]
             && b'Z' == header[4] && 0 == header[5] {
             FileType::Xz
+        } else if header.len() > 8
+            && (
+            (0x28 == header[0] && 0xb5 == header[1]
+                && 0x2f == header[2] && 0xfd == header[3]) ||
+                // a skippable frame, which may be followed by regular frames
+                (0x50 == (header[0] & 0xf0) && 0x2a == header[1]
+                    && 0x4d == header[2] && 0x18 == header[3])
+        ) {
+            FileType::Zstd
+        } else if header.len() > 32
+            && sevenz::MAGIC == &header[..6] {
+            FileType::SevenZip
+        } else if header.len() > 96
+            && rpm::LEAD_MAGIC == &header[..4] {
+            FileType::Rpm
+        } else if header.len() > 96
+            && squashfs::MAGIC == &header[..4]
+            // major version, 4
+            && 4 == header[28] && 0 == header[29] {
+            FileType::SquashFs
+        } else if cpio::Format::identify(header).is_some() {
+            FileType::Cpio
         } else if is_probably_tar(header) {
             FileType::Tar
+        } else if fat::is_fat(header) {
+            // a FAT boot sector looks just like a partition table, so check it first
+            FileType::Fat
         } else if header.len() > 512
             && 0x55 == header[510] && 0xaa == header[511] {
             FileType::DiskImage
         } else if header.len() > 2048
             && 0x53 == header[0x438] && 0xef == header[0x439] {
             FileType::Ext4
+        } else if header.len() >= 512
+            && header.iter().all(|&b| 0 == b) {
+            // the volume descriptors are 32kB in, beyond what we can see here,
+            // but the "system area" before them is almost always empty.
+            // process_iso rewinds if they're not there.
+            FileType::Iso9660
         } else {
             FileType::Other
         }
@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::name::Name;
+use crate::slist::SList;
+
+/// `--include` and `--exclude`.
+///
+/// An excluded entry isn't emitted, and nothing inside it is even read.
+/// If there are includes, an entry is only emitted if it, or a container it's in, matches one;
+/// we still have to look inside the rest, in case something in there matches.
+#[derive(Default)]
+pub struct Filter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+/// Like `.gitignore`: without a `/`, the pattern matches any component of the name;
+/// otherwise it matches the inner path, or the chain of paths joined with `/`,
+/// e.g. `foo.deb/data.tar/usr/bin/foo`. `**` matches across `/`s, and `*` and `?` don't.
+/// The chain starts with the input as it was given, e.g. `/tmp/foo.deb`, or just its file name.
+struct Glob {
+    regex: Regex,
+    basename: bool,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Result<Glob> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if Some(&'*') == chars.peek() => {
+                    chars.next();
+                    if Some(&'/') == chars.peek() {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => {
+                    regex.push('[');
+                    if Some(&'!') == chars.peek() {
+                        chars.next();
+                        regex.push('^');
+                    }
+                    for c in chars.by_ref() {
+                        if ']' == c {
+                            break;
+                        }
+                        if '\\' == c || '[' == c {
+                            regex.push('\\');
+                        }
+                        regex.push(c);
+                    }
+                    regex.push(']');
+                }
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Ok(Glob {
+            regex: Regex::new(&regex).with_context(|| format!("invalid pattern: {:?}", pattern))?,
+            basename: !pattern.contains('/'),
+        })
+    }
+
+    /// Directories in archives are usually just entries before the files in them,
+    /// so a pattern matching a directory in the inner path matches everything under it.
+    fn matches(&self, path: &SList<Name>) -> bool {
+        let inner = normalise(&path.inner().text);
+        if self.basename {
+            return inner.split('/').any(|name| self.regex.is_match(name));
+        }
+
+        let outer: Vec<String> = path
+            .iter()
+            .skip(1)
+            .map(|name| normalise(&name.text).to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+        let mut outer: Vec<&str> = outer.iter().rev().map(|name| name.as_str()).collect();
+        let mut chains = vec![outer.join("/")];
+        if let Some((_, input)) = outer.first().and_then(|input| input.rsplit_once('/')) {
+            outer[0] = input;
+            chains.push(outer.join("/"));
+        }
+
+        inner
+            .match_indices('/')
+            .map(|(end, _)| &inner[..end])
+            .chain(std::iter::once(inner))
+            .any(|dir| {
+                self.regex.is_match(dir)
+                    || chains.iter().any(|outer| {
+                        !outer.is_empty() && self.regex.is_match(&format!("{}/{}", outer, dir))
+                    })
+            })
+    }
+}
+
+/// Archives store "./usr/bin/", "usr/bin/" and "/usr/bin" for the same thing.
+fn normalise(path: &str) -> &str {
+    let path = path.trim_end_matches('/');
+    let path = path.strip_prefix("./").unwrap_or(path);
+    path.trim_start_matches('/')
+}
+
+impl Filter {
+    pub fn new(include: &[&str], exclude: &[&str]) -> Result<Filter> {
+        Ok(Filter {
+            include: include
+                .iter()
+                .map(|pattern| Glob::new(pattern))
+                .collect::<Result<_>>()?,
+            exclude: exclude
+                .iter()
+                .map(|pattern| Glob::new(pattern))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn excludes(&self, path: &SList<Name>) -> bool {
+        self.exclude.iter().any(|glob| glob.matches(path))
+    }
+
+    /// Only about this entry; the caller has to remember if a container matched.
+    pub fn includes(&self, path: &SList<Name>) -> bool {
+        self.include.is_empty() || self.include.iter().any(|glob| glob.matches(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(chain: &[&str]) -> SList<Name> {
+        let mut path = SList::head(Name::new(chain[0]));
+        for name in &chain[1..] {
+            path = path.plus(Name::new(name));
+        }
+        path
+    }
+
+    #[test]
+    fn globs() {
+        let filter = Filter::new(&["*.so", "usr/share/doc/**"], &["foo.deb/**/*.gz"]).unwrap();
+        let so = path(&["foo.deb", "data.tar.xz", "", "./usr/lib/libfoo.so"]);
+        assert!(filter.includes(&so));
+        assert!(!filter.excludes(&so));
+
+        assert!(filter.includes(&path(&["a.deb", "data.tar", "./usr/share/doc/a/README"])));
+        assert!(!filter.includes(&path(&["a.deb", "data.tar", "./usr/share/docs"])));
+        assert!(!filter.includes(&path(&["a.deb", "data.tar", "./usr/lib/libfoo.so.1"])));
+        assert!(filter.includes(&path(&["a.deb", "data.tar", "./usr/lib/foo.so/x"])));
+
+        assert!(filter.excludes(&path(&["foo.deb", "data.tar.gz"])));
+        assert!(filter.excludes(&path(&["foo.deb", "data.tar", "./usr/x.gz"])));
+        assert!(!filter.excludes(&path(&["bar.deb", "data.tar.gz"])));
+        assert!(filter.excludes(&path(&["/tmp/foo.deb", "data.tar.gz"])));
+        assert!(!filter.excludes(&path(&["/tmp/bar.deb", "data.tar.gz"])));
+
+        let filter = Filter::new(&[], &["tmp/foo.deb/*.gz"]).unwrap();
+        assert!(filter.excludes(&path(&["/tmp/foo.deb", "data.tar.gz"])));
+        assert!(!filter.excludes(&path(&["foo.deb", "data.tar.gz"])));
+    }
+
+    #[test]
+    fn classes() {
+        let filter = Filter::new(&["li?[!c].[ao]"], &[]).unwrap();
+        assert!(filter.includes(&path(&["libz.a"])));
+        assert!(!filter.includes(&path(&["libc.a"])));
+        assert!(!filter.includes(&path(&["libz.so"])));
+    }
+}
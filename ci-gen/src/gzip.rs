@@ -0,0 +1,85 @@
+use std::io;
+use std::io::Read;
+
+pub use libflate::gzip::{Decoder, Header};
+
+/// Concatenated members (from `pigz`, or `cat a.gz b.gz`) are one stream, as `gzip -d` sees it.
+///
+/// libflate's `MultiDecoder` fails if there's anything after the last member;
+/// like `gzip -d`, we ignore anything that doesn't look like another member, e.g. padding.
+pub struct MultiDecoder<R> {
+    header: Header,
+    decoder: Option<Decoder<R>>,
+}
+
+impl<R: io::BufRead> MultiDecoder<R> {
+    pub fn new(inner: R) -> io::Result<MultiDecoder<R>> {
+        let decoder = Decoder::new(inner)?;
+        Ok(MultiDecoder {
+            header: decoder.header().clone(),
+            decoder: Some(decoder),
+        })
+    }
+
+    /// The header of the first member; later members' are rarely interesting.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl<R: io::BufRead> Read for MultiDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let found = match self.decoder {
+                Some(ref mut decoder) => decoder.read(buf)?,
+                None => return Ok(0),
+            };
+
+            if 0 != found || buf.is_empty() {
+                return Ok(found);
+            }
+
+            let mut inner = self.decoder.take().unwrap().into_inner();
+            if inner.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+                self.decoder = Some(Decoder::new(inner)?);
+            }
+        }
+    }
+}
+
+/// Extra subfield ids are conventionally two letters, e.g. "RA" or "BC".
+pub fn subfield_name(id: [u8; 2]) -> String {
+    if id.iter().all(|b| b.is_ascii_alphanumeric()) {
+        format!("{}{}", id[0] as char, id[1] as char)
+    } else {
+        format!("{:02x}{:02x}", id[0], id[1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+
+    use super::*;
+
+    fn member(data: &[u8]) -> Vec<u8> {
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+
+    #[test]
+    fn members_then_padding() {
+        let mut data = member(b"hello ");
+        data.extend(member(b"world"));
+        data.extend([0u8; 512].iter());
+
+        let mut decoded = Vec::new();
+        MultiDecoder::new(&data[..])
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(b"hello world", &decoded[..]);
+    }
+}
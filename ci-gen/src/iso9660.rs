@@ -0,0 +1,533 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+const SECTOR: u64 = 2048;
+/// The volume descriptors start after a 32kB "system area", which is usually empty.
+const DESCRIPTORS: u64 = 16 * SECTOR;
+const MAGIC: &[u8] = b"CD001";
+
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_ASSOCIATED: u8 = 0x04;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Names {
+    /// Rock Ridge, with this many bytes to skip at the start of each system use area.
+    RockRidge(usize),
+    Joliet,
+    Plain,
+}
+
+pub struct Iso<R> {
+    inner: R,
+    root: (u32, u32),
+    names: Names,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Entry {
+    pub path: String,
+    pub is_dir: bool,
+    /// Everything else is only available with Rock Ridge.
+    pub mode: Option<u32>,
+    pub uid: u32,
+    pub gid: u32,
+    pub device: (u32, u32),
+    pub link: Option<String>,
+    pub atime: Option<time::OffsetDateTime>,
+    pub mtime: Option<time::OffsetDateTime>,
+    pub ctime: Option<time::OffsetDateTime>,
+    pub btime: Option<time::OffsetDateTime>,
+    /// Large files are split into many (consecutive) directory records.
+    extents: Vec<(u32, u32)>,
+}
+
+struct Record {
+    extent: u32,
+    len: u32,
+    recorded: [u8; 7],
+    flags: u8,
+    name: Vec<u8>,
+    system_use: Vec<u8>,
+}
+
+fn invalid<T: AsRef<str>>(msg: T) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.as_ref())
+}
+
+/// Most numbers are stored twice, little endian then big endian; we only read the first copy.
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// How much of the start of an image `is_iso_header` needs.
+pub const HEADER_LEN: usize = DESCRIPTORS as usize + 1 + MAGIC.len();
+
+/// `is_iso`, for a stream which can't seek.
+pub fn is_iso_header(header: &[u8]) -> bool {
+    header.len() >= HEADER_LEN && MAGIC == &header[DESCRIPTORS as usize + 1..HEADER_LEN]
+}
+
+pub fn is_iso<R: Read + Seek>(from: &mut R) -> io::Result<bool> {
+    from.seek(SeekFrom::Start(DESCRIPTORS + 1))?;
+    let mut magic = [0u8; 5];
+    let found = crate::tee::read_all(&mut *from, &mut magic)?;
+    from.seek(SeekFrom::Start(0))?;
+    Ok(MAGIC == &magic[..found])
+}
+
+fn parse_record(buf: &[u8]) -> io::Result<Record> {
+    if buf.len() < 34 || buf.len() < 33 + buf[32] as usize {
+        return Err(invalid("truncated directory record"));
+    }
+
+    let name_len = buf[32] as usize;
+    // the name is padded to make the system use area start on an even offset
+    let system_use = 33 + name_len + (1 - name_len % 2);
+
+    let mut recorded = [0u8; 7];
+    recorded.copy_from_slice(&buf[18..25]);
+
+    Ok(Record {
+        extent: le32(&buf[2..]),
+        len: le32(&buf[10..]),
+        recorded,
+        flags: buf[25],
+        name: buf[33..33 + name_len].to_vec(),
+        system_use: buf.get(system_use..).unwrap_or_default().to_vec(),
+    })
+}
+
+/// The seven-byte form used in directory records.
+fn short_time(val: &[u8]) -> Option<time::OffsetDateTime> {
+    if val.iter().all(|&b| 0 == b) {
+        return None;
+    }
+
+    let date = time::Date::from_calendar_date(
+        1900 + i32::from(val[0]),
+        time::Month::try_from(val[1]).ok()?,
+        val[2],
+    )
+    .ok()?;
+
+    // offset from GMT, in fifteen minute intervals
+    let offset = time::UtcOffset::from_whole_seconds(i32::from(val[6] as i8) * 15 * 60).ok()?;
+
+    Some(
+        date.with_hms(val[3], val[4], val[5])
+            .ok()?
+            .assume_offset(offset),
+    )
+}
+
+/// The seventeen-byte form used in the volume descriptor (and sometimes by Rock Ridge):
+/// `YYYYMMDDHHMMSScc` in ascii, then the offset.
+fn long_time(val: &[u8]) -> Option<time::OffsetDateTime> {
+    let digits = std::str::from_utf8(&val[..16]).ok()?;
+    let field = |start: usize, len: usize| digits.get(start..start + len)?.parse::<u32>().ok();
+
+    let date = time::Date::from_calendar_date(
+        field(0, 4)? as i32,
+        time::Month::try_from(field(4, 2)? as u8).ok()?,
+        field(6, 2)? as u8,
+    )
+    .ok()?;
+
+    let offset = time::UtcOffset::from_whole_seconds(i32::from(val[16] as i8) * 15 * 60).ok()?;
+
+    Some(
+        date.with_hms_milli(
+            field(8, 2)? as u8,
+            field(10, 2)? as u8,
+            field(12, 2)? as u8,
+            field(14, 2)? as u16 * 10,
+        )
+        .ok()?
+        .assume_offset(offset),
+    )
+}
+
+/// Strip the `;1` version, and the dot that's required even if there's no extension.
+fn plain_name(name: &str) -> &str {
+    let name = match name.rfind(';') {
+        Some(semi) => &name[..semi],
+        None => name,
+    };
+    name.strip_suffix('.').unwrap_or(name)
+}
+
+impl<R: Read + Seek> Iso<R> {
+    pub fn open(mut inner: R) -> io::Result<Iso<R>> {
+        let mut primary = None;
+        let mut joliet = None;
+
+        // there's no limit on the number of descriptors, but a real image only has a few
+        for i in 0..64 {
+            let mut desc = [0u8; SECTOR as usize];
+            inner.seek(SeekFrom::Start(DESCRIPTORS + i * SECTOR))?;
+            inner.read_exact(&mut desc)?;
+
+            if MAGIC != &desc[1..6] {
+                return Err(invalid("invalid volume descriptor"));
+            }
+
+            let root = parse_record(&desc[156..190])?;
+            match desc[0] {
+                1 => primary = Some((root.extent, root.len)),
+                // a supplementary descriptor, which is Joliet if it declares UCS-2
+                2 if [b"%/@", b"%/C", b"%/E"].contains(&&[desc[88], desc[89], desc[90]]) => {
+                    joliet = Some((root.extent, root.len))
+                }
+                255 => break,
+                _ => (),
+            }
+        }
+
+        let primary = primary.ok_or_else(|| invalid("no primary volume descriptor"))?;
+
+        let mut iso = Iso {
+            inner,
+            root: primary,
+            names: Names::Plain,
+        };
+
+        // Rock Ridge is announced by an "SP" record on the root's "." entry
+        let dot = iso.read_records(primary)?.into_iter().next();
+        if let Some(dot) = dot {
+            let sp = &dot.system_use;
+            if sp.len() >= 7 && b"SP" == &sp[..2] && 0xbe == sp[4] && 0xef == sp[5] {
+                iso.names = Names::RockRidge(sp[6] as usize);
+                return Ok(iso);
+            }
+        }
+
+        if let Some(joliet) = joliet {
+            iso.root = joliet;
+            iso.names = Names::Joliet;
+        }
+
+        Ok(iso)
+    }
+
+    fn read_records(&mut self, (extent, len): (u32, u32)) -> io::Result<Vec<Record>> {
+        if len > 64 * 1024 * 1024 {
+            return Err(invalid(format!("unreasonable directory size: {}", len)));
+        }
+
+        let mut data = vec![0u8; len as usize];
+        self.inner
+            .seek(SeekFrom::Start(u64::from(extent) * SECTOR))?;
+        self.inner.read_exact(&mut data)?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let record_len = data[pos] as usize;
+
+            // records don't cross sector boundaries; the rest of the sector is zeros
+            if 0 == record_len {
+                pos = (pos / SECTOR as usize + 1) * SECTOR as usize;
+                continue;
+            }
+
+            let end = pos + record_len;
+            if end > data.len() {
+                return Err(invalid("directory record overflows directory"));
+            }
+
+            records.push(parse_record(&data[pos..end])?);
+            pos = end;
+        }
+
+        Ok(records)
+    }
+
+    /// Every file and directory in the image, parents before their children.
+    pub fn entries(&mut self) -> io::Result<Vec<Entry>> {
+        let mut ret = Vec::new();
+        let mut seen = HashSet::new();
+        let mut todo = vec![(String::new(), self.root)];
+
+        while let Some((prefix, dir)) = todo.pop() {
+            // a corrupt (or malicious) image can have loops
+            if !seen.insert(dir.0) {
+                continue;
+            }
+
+            let mut children: Vec<Entry> = Vec::new();
+            let mut continuing = false;
+
+            for record in self.read_records(dir)? {
+                if continuing {
+                    let last = children.last_mut().expect("continuing implies an entry");
+                    last.extents.push((record.extent, record.len));
+                    continuing = 0 != record.flags & FLAG_MULTI_EXTENT;
+                    continue;
+                }
+
+                // "." and "..", and resource forks
+                if [0] == record.name[..]
+                    || [1] == record.name[..]
+                    || 0 != record.flags & FLAG_ASSOCIATED
+                {
+                    continue;
+                }
+
+                let mut entry = Entry {
+                    is_dir: 0 != record.flags & FLAG_DIRECTORY,
+                    mtime: short_time(&record.recorded),
+                    extents: vec![(record.extent, record.len)],
+                    ..Entry::default()
+                };
+
+                let name = match self.names {
+                    Names::RockRidge(skip) => {
+                        let rr = self.rock_ridge(&record.system_use, skip, &mut entry)?;
+                        if rr.relocated {
+                            // this is the real location of a deep directory, found via its `CL`
+                            continue;
+                        }
+                        rr.name.unwrap_or_else(|| {
+                            plain_name(&String::from_utf8_lossy(&record.name)).to_string()
+                        })
+                    }
+                    Names::Joliet => {
+                        let units: Vec<u16> = record
+                            .name
+                            .chunks(2)
+                            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+                            .collect();
+                        plain_name(&String::from_utf16_lossy(&units)).to_string()
+                    }
+                    Names::Plain => plain_name(&String::from_utf8_lossy(&record.name)).to_string(),
+                };
+
+                entry.path = format!("{}{}", prefix, name);
+                children.push(entry);
+                // only once there's an entry to continue
+                continuing = 0 != record.flags & FLAG_MULTI_EXTENT;
+            }
+
+            for child in &children {
+                if child.is_dir {
+                    todo.push((format!("{}/", child.path), child.extents[0]));
+                }
+            }
+
+            ret.extend(children);
+        }
+
+        Ok(ret)
+    }
+
+    fn rock_ridge(
+        &mut self,
+        system_use: &[u8],
+        skip: usize,
+        entry: &mut Entry,
+    ) -> io::Result<RockRidge> {
+        let mut rr = RockRidge::default();
+        let mut area = system_use.get(skip..).unwrap_or_default().to_vec();
+        let mut link: Vec<String> = Vec::new();
+        let mut link_open = false;
+
+        // continuation areas can chain, but not far
+        for _ in 0..16 {
+            let mut continuation = None;
+            let mut pos = 0;
+            while pos + 4 <= area.len() {
+                let len = area[pos + 2] as usize;
+                if len < 4 || pos + len > area.len() {
+                    break;
+                }
+                let field = &area[pos..pos + len];
+                pos += len;
+
+                match &field[..2] {
+                    b"PX" if len >= 36 => {
+                        entry.mode = Some(le32(&field[4..]));
+                        entry.uid = le32(&field[20..]);
+                        entry.gid = le32(&field[28..]);
+                    }
+                    b"PN" if len >= 20 => {
+                        let high = le32(&field[4..]);
+                        let low = le32(&field[12..]);
+                        // as the kernel does: some writers pack the whole `dev_t` into the low half
+                        entry.device = if 0 == high && 0 != low & !0xff {
+                            (low >> 8, low & 0xff)
+                        } else {
+                            (high, low)
+                        };
+                    }
+                    b"SL" if len >= 5 => {
+                        let mut components = &field[5..];
+                        while components.len() >= 2 {
+                            let flags = components[0];
+                            let text_len = (components[1] as usize).min(components.len() - 2);
+                            let text = match flags & 0x0e {
+                                0x02 => ".".to_string(),
+                                0x04 => "..".to_string(),
+                                0x08 => String::new(),
+                                _ => String::from_utf8_lossy(&components[2..2 + text_len])
+                                    .to_string(),
+                            };
+                            match link.last_mut() {
+                                Some(last) if link_open => last.push_str(&text),
+                                _ => link.push(text),
+                            }
+                            link_open = 0 != flags & 0x01;
+                            components = &components[2 + text_len..];
+                        }
+                    }
+                    // flags 0x02 and 0x04 are "." and "..", which we skip anyway
+                    b"NM" if len >= 5 && 0 == field[4] & 0x06 => {
+                        let part = String::from_utf8_lossy(&field[5..]);
+                        rr.name.get_or_insert_with(String::new).push_str(&part);
+                    }
+                    b"TF" if len >= 5 => {
+                        let flags = field[4];
+                        let size = if 0 != flags & 0x80 { 17 } else { 7 };
+                        let mut stamps = field[5..].chunks_exact(size);
+                        let targets = [
+                            &mut entry.btime,
+                            &mut entry.mtime,
+                            &mut entry.atime,
+                            &mut entry.ctime,
+                        ];
+                        for (bit, target) in targets.into_iter().enumerate() {
+                            if 0 == flags & (1 << bit) {
+                                continue;
+                            }
+                            if let Some(stamp) = stamps.next() {
+                                *target = if 17 == size {
+                                    long_time(stamp)
+                                } else {
+                                    short_time(stamp)
+                                };
+                            }
+                        }
+                    }
+                    b"CL" if len >= 12 => {
+                        // a deep directory has been moved elsewhere; this placeholder points at it
+                        let extent = le32(&field[4..]);
+                        let dot = self.read_records((extent, SECTOR as u32))?;
+                        let len = dot.first().map(|dot| dot.len).unwrap_or(0);
+                        entry.is_dir = true;
+                        entry.extents = vec![(extent, len)];
+                    }
+                    b"RE" => rr.relocated = true,
+                    b"CE" if len >= 28 => {
+                        continuation =
+                            Some((le32(&field[4..]), le32(&field[12..]), le32(&field[20..])))
+                    }
+                    b"ST" => break,
+                    _ => (),
+                }
+            }
+
+            let (block, offset, len) = match continuation {
+                Some(continuation) => continuation,
+                None => break,
+            };
+
+            if len > SECTOR as u32 {
+                return Err(invalid("unreasonable rock ridge continuation"));
+            }
+
+            area = vec![0u8; len as usize];
+            self.inner.seek(SeekFrom::Start(
+                u64::from(block) * SECTOR + u64::from(offset),
+            ))?;
+            self.inner.read_exact(&mut area)?;
+        }
+
+        if !link.is_empty() {
+            entry.link = Some(link.join("/"));
+        }
+
+        Ok(rr)
+    }
+
+    pub fn open_file<'i>(&'i mut self, entry: &Entry) -> impl Read + 'i {
+        Extents {
+            inner: &mut self.inner,
+            extents: entry.extents.clone(),
+            next: 0,
+            remaining: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RockRidge {
+    name: Option<String>,
+    relocated: bool,
+}
+
+struct Extents<'i, R> {
+    inner: &'i mut R,
+    extents: Vec<(u32, u32)>,
+    next: usize,
+    remaining: u64,
+}
+
+impl<'i, R: Read + Seek> Read for Extents<'i, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while 0 == self.remaining {
+            let (extent, len) = match self.extents.get(self.next) {
+                Some(&next) => next,
+                None => return Ok(0),
+            };
+            self.next += 1;
+            self.inner
+                .seek(SeekFrom::Start(u64::from(extent) * SECTOR))?;
+            self.remaining = u64::from(len);
+        }
+
+        let wanted = (buf.len() as u64).min(self.remaining) as usize;
+        let found = self.inner.read(&mut buf[..wanted])?;
+        if 0 == found {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= found as u64;
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!("README.TXT", plain_name("README.TXT;1"));
+        assert_eq!("MAKEFILE", plain_name("MAKEFILE.;1"));
+        assert_eq!("BOOT", plain_name("BOOT"));
+    }
+
+    #[test]
+    fn times() {
+        let short = short_time(&[117, 7, 14, 3, 40, 0, 4]).unwrap();
+        assert_eq!(1500000000, short.unix_timestamp());
+
+        let long = long_time(b"2017071403400050\x04").unwrap();
+        assert_eq!(1500000000, long.unix_timestamp());
+        assert_eq!(500, long.millisecond());
+
+        assert!(short_time(&[0; 7]).is_none());
+        assert!(long_time(b"0000000000000000\0").is_none());
+    }
+
+    #[test]
+    fn headers() {
+        let mut header = vec![0u8; HEADER_LEN];
+        assert!(!is_iso_header(&header));
+        header[DESCRIPTORS as usize + 1..].copy_from_slice(MAGIC);
+        assert!(is_iso_header(&header));
+        assert!(!is_iso_header(&header[..HEADER_LEN - 1]));
+    }
+}
@@ -0,0 +1,142 @@
+use std::cell::Cell;
+
+use anyhow::{bail, Result};
+
+use crate::errors::ErrorKind;
+
+/// How far one input may expand, so an untrusted upload can't fill the disk.
+/// Bytes are what we decompress into memory or temp files to look inside; `None` is no limit.
+#[derive(Default)]
+pub struct Limits {
+    /// Bytes buffered for any one entry.
+    pub size: Option<u64>,
+    /// Bytes buffered for an input, in total, including entries inside entries.
+    pub total: Option<u64>,
+    /// Entries emitted for an input.
+    pub entries: Option<u64>,
+    /// `total`, as a multiple of the input's own size.
+    pub ratio: Option<u64>,
+}
+
+/// What one input has used so far, across all its unpackers.
+#[derive(Default)]
+pub struct Usage {
+    pub emitted: Cell<u64>,
+    expanded: Cell<u64>,
+    /// `None` while we're still reading the input itself, e.g. from stdin.
+    input_len: Cell<Option<u64>>,
+}
+
+impl Usage {
+    pub fn new(input_len: Option<u64>) -> Usage {
+        Usage {
+            input_len: Cell::new(input_len),
+            ..Usage::default()
+        }
+    }
+
+    pub fn set_input_len(&self, len: u64) {
+        self.input_len.set(Some(len));
+    }
+}
+
+impl Limits {
+    /// Account for `more` bytes being buffered, for an entry which already has `so_far`.
+    pub fn charge(&self, usage: &Usage, so_far: u64, more: u64) -> Result<()> {
+        let input_len = match usage.input_len.get() {
+            Some(len) => len,
+            None => return Ok(()),
+        };
+
+        let expanded = usage.expanded.get().saturating_add(more);
+        usage.expanded.set(expanded);
+
+        if let Some(size) = self.size.filter(|&size| so_far.saturating_add(more) > size) {
+            bail!(ErrorKind::Limit(format!(
+                "entry larger than {} bytes",
+                size
+            )));
+        }
+        if let Some(total) = self.total.filter(|&total| expanded > total) {
+            bail!(ErrorKind::Limit(format!(
+                "input expands past {} bytes",
+                total
+            )));
+        }
+        if let Some(ratio) = self
+            .ratio
+            .filter(|&ratio| expanded > input_len.max(1).saturating_mul(ratio))
+        {
+            bail!(ErrorKind::Limit(format!(
+                "input expands more than {} times",
+                ratio
+            )));
+        }
+        Ok(())
+    }
+
+    /// Before emitting another entry; this stops the whole input, rather than one container.
+    pub fn check_entries(&self, usage: &Usage) -> Result<()> {
+        if let Some(entries) = self
+            .entries
+            .filter(|&entries| usage.emitted.get() >= entries)
+        {
+            bail!(ErrorKind::EntryLimit(format!(
+                "more than {} entries",
+                entries
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A number of bytes, with an optional binary suffix: "512", "64k", "10G".
+pub fn parse_size(value: &str) -> Option<u64> {
+    let (number, shift) = match value.char_indices().last()? {
+        (end, 'k') | (end, 'K') => (&value[..end], 10),
+        (end, 'm') | (end, 'M') => (&value[..end], 20),
+        (end, 'g') | (end, 'G') => (&value[..end], 30),
+        (end, 't') | (end, 'T') => (&value[..end], 40),
+        _ => (value, 0),
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(Some(512), parse_size("512"));
+        assert_eq!(Some(64 * 1024), parse_size("64k"));
+        assert_eq!(Some(10 << 30), parse_size("10G"));
+        assert_eq!(None, parse_size("G"));
+        assert_eq!(None, parse_size("1.5M"));
+        assert_eq!(None, parse_size("99999999999T"));
+    }
+
+    #[test]
+    fn charges() {
+        let limits = Limits {
+            size: Some(100),
+            total: Some(150),
+            ratio: Some(10),
+            ..Limits::default()
+        };
+
+        // stdin, before we know how big it is
+        let usage = Usage::new(None);
+        limits.charge(&usage, 0, 1000).unwrap();
+        usage.set_input_len(10);
+
+        limits.charge(&usage, 0, 60).unwrap();
+        assert!(limits.charge(&usage, 60, 41).is_err());
+        // 101 bytes, from 10
+        assert!(limits.charge(&usage, 0, 0).is_err());
+
+        let usage = Usage::new(Some(1000));
+        limits.charge(&usage, 0, 100).unwrap();
+        assert!(limits.charge(&usage, 0, 51).is_err());
+    }
+}
@@ -1,21 +1,50 @@
-use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
 use ci_capnp::Meta;
 use clap::{App, Arg};
-use libflate::gzip;
 
+mod cpio;
+mod digest;
 mod errors;
+mod fat;
 mod filetype;
+mod filter;
+mod gzip;
+mod iso9660;
+mod limits;
+mod name;
 mod output_capnp;
+mod output_json;
+mod pax;
+mod rpm;
+mod sevenz;
 mod simple_time;
 mod slist;
+mod sparse;
+mod squashfs;
 mod stat;
 mod tee;
 mod unpacker;
+mod zip_extra;
+
+pub enum OutputFormat {
+    Capnp,
+    /// One JSON object per line, for tools without capnp.
+    Jsonl,
+}
 
 pub struct Options {
     content_output: bool,
+    format: OutputFormat,
     max_depth: u32,
     verbose: u8,
+    filter: filter::Filter,
+    limits: limits::Limits,
+    digests: Vec<digest::Algorithm>,
 }
 
 enum ArchiveReadFailure {
@@ -24,7 +53,7 @@ enum ArchiveReadFailure {
 }
 
 pub struct EntryBuilder {
-    path: slist::SList<String>,
+    path: slist::SList<name::Name>,
     failure: Option<ArchiveReadFailure>,
     depth: u32,
     meta: Meta,
@@ -58,6 +87,14 @@ fn real_main() -> Result<i32> {
                 .long("list")
                 .help("Show headers only, not object content"),
         )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(["capnp", "jsonl"])
+                .default_value("capnp")
+                .help("Output format; jsonl has the content inline, as base64"),
+        )
         .arg(
             Arg::with_name("max-depth")
                 .short('d')
@@ -72,10 +109,77 @@ fn real_main() -> Result<i32> {
                 })
                 .help("Limit recursion. 1: like unzip. Default: lots"),
         )
+        .arg(
+            Arg::with_name("hash")
+                .long("hash")
+                .takes_value(true)
+                .value_name("ALGORITHMS")
+                .validator(valid_algorithms)
+                .help(
+                    "Record digests of the content: crc32c, sha1, sha256, sha512, blake3; comma separated",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-size")
+                .long("max-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(valid_size)
+                .help("Don't unpack entries bigger than this, e.g. 512M"),
+        )
+        .arg(
+            Arg::with_name("max-total")
+                .long("max-total")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(valid_size)
+                .help("Stop unpacking an input after this much has been decompressed"),
+        )
+        .arg(
+            Arg::with_name("max-entries")
+                .long("max-entries")
+                .takes_value(true)
+                .value_name("N")
+                .validator(valid_size)
+                .help("Stop unpacking an input after this many entries"),
+        )
+        .arg(
+            Arg::with_name("max-ratio")
+                .long("max-ratio")
+                .takes_value(true)
+                .value_name("N")
+                .validator(valid_size)
+                .help("Stop unpacking an input which decompresses to N times its size"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .takes_value(true)
+                .value_name("GLOB")
+                .multiple_occurrences(true)
+                .help("Only emit entries matching GLOB, or inside something matching it"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .value_name("GLOB")
+                .multiple_occurrences(true)
+                .help("Skip entries matching GLOB, and everything inside them"),
+        )
+        .arg(
+            Arg::with_name("files-from")
+                .short('T')
+                .long("files-from")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Also process the files named in FILE (- for stdin), by line or NUL"),
+        )
         .arg(
             Arg::with_name("INPUT")
-                .required(true)
-                .help("File(s) to process")
+                .required_unless_present("files-from")
+                .allow_invalid_utf8(true)
+                .help("File(s) to process; - for stdin")
                 .multiple(true),
         )
         .get_matches();
@@ -84,18 +188,175 @@ fn real_main() -> Result<i32> {
 
     let options = Options {
         content_output,
+        format: match matches.value_of("format") {
+            Some("jsonl") => OutputFormat::Jsonl,
+            _ => OutputFormat::Capnp,
+        },
         max_depth: matches.value_of("max-depth").unwrap().parse().unwrap(),
         verbose: must_fit(1 + matches.occurrences_of("verbose") - matches.occurrences_of("quiet")),
+        filter: filter::Filter::new(
+            &matches
+                .values_of("include")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+            &matches
+                .values_of("exclude")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+        )?,
+        limits: limits::Limits {
+            size: matches.value_of("max-size").and_then(limits::parse_size),
+            total: matches.value_of("max-total").and_then(limits::parse_size),
+            entries: matches.value_of("max-entries").and_then(limits::parse_size),
+            ratio: matches.value_of("max-ratio").and_then(limits::parse_size),
+        },
+        digests: matches
+            .value_of("hash")
+            .map(|list| {
+                list.split(',')
+                    .filter_map(digest::Algorithm::from_name)
+                    .collect()
+            })
+            .unwrap_or_default(),
     };
 
-    for path in matches.values_of("INPUT").unwrap() {
-        unpacker::process_real_path(path, &options)
-            .with_context(|| format!("processing: '{}'", path))?;
+    // `None` is stdin, which only the command line can ask for
+    let inputs: Vec<Option<PathBuf>> = matches
+        .values_of_os("INPUT")
+        .into_iter()
+        .flatten()
+        .map(|path| match path.to_str() {
+            Some("-") => None,
+            _ => Some(PathBuf::from(path)),
+        })
+        .collect();
+
+    let list = matches.value_of("files-from");
+    if Some("-") == list && inputs.contains(&None) {
+        bail!("stdin can't be both the list of files (-T -) and an input (-)");
+    }
+
+    let mut links = unpacker::HardLinks::default();
+    let mut process = |path: Option<PathBuf>| -> Result<()> {
+        let path = match path {
+            Some(path) => path,
+            None => return unpacker::process_stdin(&options).with_context(|| "processing stdin"),
+        };
+
+        unpacker::process_real_path(&path, &options, &mut links)
+            .with_context(|| format!("processing: '{}'", path.display()))
+    };
+
+    for path in inputs {
+        process(path)?;
+    }
+
+    // each as it's read, so the list can come from something slow, like `find`
+    if let Some(list) = list {
+        let context = || format!("reading list: '{}'", list);
+        let from: Box<dyn BufRead> = if "-" == list {
+            Box::new(io::stdin().lock())
+        } else {
+            Box::new(io::BufReader::new(
+                fs::File::open(list).with_context(context)?,
+            ))
+        };
+        for path in file_list(from).with_context(context)? {
+            process(Some(path.with_context(context)?))?;
+        }
     }
 
     Ok(0)
 }
 
+fn valid_size(val: &str) -> Result<(), String> {
+    match limits::parse_size(val) {
+        Some(_) => Ok(()),
+        None => Err("must be a number, optionally with a k, M, G or T suffix".to_string()),
+    }
+}
+
+fn valid_algorithms(val: &str) -> Result<(), String> {
+    match val
+        .split(',')
+        .find(|name| digest::Algorithm::from_name(name).is_none())
+    {
+        Some(name) => Err(format!("unsupported hash: {:?}", name)),
+        None => Ok(()),
+    }
+}
+
+/// Like `find -print0`, or `find`: if the first name ends with a NUL, they all do, and newlines
+/// are part of the names. The names are taken literally: a `-` is a file called `-`, not stdin.
+fn file_list<R: BufRead>(mut from: R) -> io::Result<impl Iterator<Item = io::Result<PathBuf>>> {
+    let mut first = Vec::new();
+    let separator = loop {
+        let buf = from.fill_buf()?;
+        if buf.is_empty() {
+            break b'\n';
+        }
+        match buf.iter().position(|&b| 0 == b || b'\n' == b) {
+            Some(end) => {
+                let separator = buf[end];
+                first.extend_from_slice(&buf[..end]);
+                from.consume(end + 1);
+                break separator;
+            }
+            None => {
+                let len = buf.len();
+                first.extend_from_slice(buf);
+                from.consume(len);
+            }
+        }
+    };
+
+    Ok(std::iter::once(Ok(first))
+        .chain(from.split(separator))
+        .filter(|name| !matches!(name, Ok(name) if name.is_empty()))
+        .map(|name| name.map(|name| path_from_bytes(&name))))
+}
+
+#[cfg(unix)]
+fn path_from_bytes(name: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(name))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(name: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).to_string())
+}
+
 fn main() -> Result<()> {
     std::process::exit(real_main()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn parse_file_list(data: &[u8]) -> Vec<PathBuf> {
+        file_list(data).unwrap().collect::<io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn file_lists() {
+        assert_eq!(paths(&["a", "b c"]), parse_file_list(b"a\nb c\n"));
+        assert_eq!(paths(&["a", "b\nc"]), parse_file_list(b"a\0b\nc\0"));
+        assert_eq!(paths(&["-", "./-"]), parse_file_list(b"-\n\n./-"));
+        assert!(parse_file_list(b"").is_empty());
+
+        // the first name decides, even if it's longer than what's buffered
+        let long = "a".repeat(100);
+        let list = format!("{}\0b\nc\0", long);
+        let from = io::BufReader::with_capacity(16, list.as_bytes());
+        let found: Vec<PathBuf> = file_list(from).unwrap().map(Result::unwrap).collect();
+        assert_eq!(paths(&[&long, "b\nc"]), found);
+    }
+}
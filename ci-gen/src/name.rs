@@ -0,0 +1,75 @@
+use std::ffi::OsStr;
+use std::fmt;
+
+/// A path component, as we emit it: text, and the original bytes, if the text is only
+/// a lossy (or guessed) decoding of them.
+#[derive(Clone)]
+pub struct Name {
+    pub text: String,
+    pub raw: Option<Vec<u8>>,
+}
+
+impl Name {
+    pub fn new(text: &str) -> Name {
+        Name {
+            text: text.to_string(),
+            raw: None,
+        }
+    }
+
+    /// Bytes in an unknown encoding, which is probably UTF-8; e.g. tar or cpio names.
+    pub fn from_bytes(raw: &[u8]) -> Name {
+        Name::decoded(String::from_utf8_lossy(raw).to_string(), raw)
+    }
+
+    /// `text` is what we think `raw` says, e.g. after reading it as CP437.
+    pub fn decoded(text: String, raw: &[u8]) -> Name {
+        let raw = if text.as_bytes() == raw {
+            None
+        } else {
+            Some(raw.to_vec())
+        };
+        Name { text, raw }
+    }
+
+    #[cfg(unix)]
+    pub fn from_os(name: &OsStr) -> Name {
+        use std::os::unix::ffi::OsStrExt;
+        Name::from_bytes(name.as_bytes())
+    }
+
+    /// Windows names are UTF-16, so there are no bytes to keep.
+    #[cfg(not(unix))]
+    pub fn from_os(name: &OsStr) -> Name {
+        Name::new(&name.to_string_lossy())
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.text, f)
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.raw {
+            Some(ref raw) => write!(f, "{:?} (raw: {:?})", self.text, raw),
+            None => fmt::Debug::fmt(&self.text, f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_only_when_lossy() {
+        assert_eq!(None, Name::from_bytes("caf\u{e9}".as_bytes()).raw);
+
+        let latin1 = Name::from_bytes(b"caf\xe9");
+        assert_eq!("caf\u{fffd}", latin1.text);
+        assert_eq!(Some(b"caf\xe9".to_vec()), latin1.raw);
+    }
+}
@@ -1,14 +1,19 @@
+use std::borrow::Cow;
 use std::io;
 
 use anyhow::Result;
 
 use ci_capnp::entry;
+use ci_capnp::Container;
+
+use crate::ArchiveReadFailure;
 
 pub fn write_capnp<W: io::Write>(
     to: &mut W,
     current: &crate::EntryBuilder,
     content_output: bool,
     size: u64,
+    digests: &[(String, Vec<u8>)],
 ) -> Result<()> {
     let mut message = capnp::message::Builder::new_default();
     {
@@ -20,7 +25,16 @@ pub fn write_capnp<W: io::Write>(
             let mut paths = entry.reborrow().init_paths(current.depth + 1);
             for (i, path) in current.path.iter().enumerate() {
                 assert!(i < std::u32::MAX as usize);
-                paths.set(i as u32, path.as_str());
+                paths.set(i as u32, path.text.as_str());
+            }
+        }
+
+        if current.path.iter().any(|path| path.raw.is_some()) {
+            let mut raw_paths = entry.reborrow().init_raw_paths(current.depth + 1);
+            for (i, path) in current.path.iter().enumerate() {
+                if let Some(ref raw) = path.raw {
+                    raw_paths.set(i as u32, raw);
+                }
             }
         }
 
@@ -33,7 +47,29 @@ pub fn write_capnp<W: io::Write>(
             }
         }
 
-        ci_capnp::write_meta(&current.meta, &mut entry, size)?;
+        if !digests.is_empty() {
+            let mut list = entry.reborrow().init_digests(digests.len() as u32);
+            for (i, (algorithm, value)) in digests.iter().enumerate() {
+                let mut digest = list.reborrow().get(i as u32);
+                digest.set_algorithm(algorithm.as_str());
+                digest.set_value(value);
+            }
+        }
+
+        // a failure to read it as a container overrides whatever the meta says
+        let meta = match current.failure {
+            Some(ref failure) => {
+                let mut meta = current.meta.clone();
+                meta.container = match failure {
+                    ArchiveReadFailure::Open(msg) => Container::OpenError(msg.clone()),
+                    ArchiveReadFailure::Read(msg) => Container::ReadError(msg.clone()),
+                };
+                Cow::Owned(meta)
+            }
+            None => Cow::Borrowed(&current.meta),
+        };
+
+        ci_capnp::write_meta(&meta, &mut entry, size)?;
     }
     capnp::serialize::write_message(to, &message)?;
     Ok(())
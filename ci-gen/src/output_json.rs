@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::io;
+
+use anyhow::{bail, Result};
+use ci_capnp::{Container, ItemType, Ownership, PosixEntity};
+
+use crate::ArchiveReadFailure;
+
+/// One line of JSON per entry, with the same fields (and names) as `entry.capnp`. Binary things,
+/// i.e. raw paths, xattr and format field values and the content, are base64; digests are hex.
+/// Times are strings, as nanoseconds since the epoch don't fit in a double.
+/// The content, if it's wanted, goes last, so it can be streamed.
+pub fn write_json<W: io::Write>(
+    to: &mut W,
+    current: &crate::EntryBuilder,
+    size: u64,
+    digests: &[(String, Vec<u8>)],
+    content: Option<&mut dyn io::Read>,
+) -> Result<()> {
+    let mut line = String::new();
+    line.push_str(&format!("{{\"len\":{},\"paths\":[", size));
+    for (i, path) in current.path.iter().enumerate() {
+        if 0 != i {
+            line.push(',');
+        }
+        string(&mut line, &path.text);
+    }
+    line.push(']');
+
+    if current.path.iter().any(|path| path.raw.is_some()) {
+        line.push_str(",\"rawPaths\":[");
+        for (i, path) in current.path.iter().enumerate() {
+            if 0 != i {
+                line.push(',');
+            }
+            match path.raw {
+                Some(ref raw) => string(&mut line, &base64(raw)),
+                None => line.push_str("null"),
+            }
+        }
+        line.push(']');
+    }
+
+    let meta = &current.meta;
+    line.push_str(&format!(
+        ",\"atime\":\"{}\",\"mtime\":\"{}\",\"ctime\":\"{}\",\"btime\":\"{}\"",
+        meta.atime, meta.mtime, meta.ctime, meta.btime
+    ));
+
+    line.push_str(",\"ownership\":");
+    match meta.ownership {
+        Ownership::Unknown => line.push_str("null"),
+        Ownership::Posix {
+            ref user,
+            ref group,
+            mode,
+        } => {
+            line.push_str("{\"user\":");
+            entity(&mut line, user);
+            line.push_str(",\"group\":");
+            entity(&mut line, group);
+            line.push_str(&format!(",\"mode\":{}}}", mode));
+        }
+    }
+
+    line.push_str(",\"type\":");
+    match meta.item_type {
+        ItemType::Unknown if 0 == size => line.push_str("\"directory\""),
+        ItemType::Unknown | ItemType::RegularFile => line.push_str("\"normal\""),
+        ItemType::Directory => line.push_str("\"directory\""),
+        ItemType::Fifo => line.push_str("\"fifo\""),
+        ItemType::Socket => line.push_str("\"socket\""),
+        ItemType::SymbolicLink(ref dest) => {
+            line.push_str("\"softLinkTo\",\"linkTo\":");
+            string(&mut line, dest);
+        }
+        ItemType::HardLink(ref dest) => {
+            line.push_str("\"hardLinkTo\",\"linkTo\":");
+            string(&mut line, dest);
+        }
+        ItemType::CharacterDevice { major, minor } => line.push_str(&format!(
+            "\"charDevice\",\"device\":{{\"major\":{},\"minor\":{}}}",
+            major, minor
+        )),
+        ItemType::BlockDevice { major, minor } => line.push_str(&format!(
+            "\"blockDevice\",\"device\":{{\"major\":{},\"minor\":{}}}",
+            major, minor
+        )),
+    }
+
+    let (container, error) = match current.failure {
+        Some(ArchiveReadFailure::Open(ref msg)) => ("openError", Some(msg)),
+        Some(ArchiveReadFailure::Read(ref msg)) => ("readError", Some(msg)),
+        None => match meta.container {
+            Container::Unrecognised => ("unrecognised", None),
+            Container::Included => ("included", None),
+            Container::OpenError(ref msg) => ("openError", Some(msg)),
+            Container::ReadError(ref msg) => ("readError", Some(msg)),
+            Container::Unpacked => ("unpacked", None),
+        },
+    };
+    line.push_str(",\"container\":");
+    string(&mut line, container);
+    if let Some(msg) = error {
+        line.push_str(",\"error\":");
+        string(&mut line, msg);
+    }
+
+    line.push_str(",\"xattrs\":");
+    binary_map(&mut line, &meta.xattrs);
+
+    if !meta.format_fields.is_empty() {
+        line.push_str(",\"formatFields\":");
+        binary_map(&mut line, &meta.format_fields);
+    }
+
+    if !digests.is_empty() {
+        line.push_str(",\"digests\":{");
+        for (i, (algorithm, value)) in digests.iter().enumerate() {
+            if 0 != i {
+                line.push(',');
+            }
+            string(&mut line, algorithm);
+            line.push(':');
+            let hex: String = value.iter().map(|b| format!("{:02x}", b)).collect();
+            string(&mut line, &hex);
+        }
+        line.push('}');
+    }
+
+    to.write_all(line.as_bytes())?;
+
+    if let Some(content) = content {
+        to.write_all(b",\"content\":\"")?;
+        let written = {
+            let mut encoder = base64::write::EncoderWriter::new(
+                &mut *to,
+                &base64::engine::general_purpose::STANDARD,
+            );
+            let written = io::copy(content, &mut encoder)?;
+            encoder.finish()?;
+            written
+        };
+        if written != size {
+            bail!(format!("expecting to write {} but wrote {}", size, written));
+        }
+        to.write_all(b"\"")?;
+    }
+
+    to.write_all(b"}\n")?;
+    Ok(())
+}
+
+/// An object, in name order, of base64 values.
+fn binary_map(line: &mut String, map: &HashMap<String, Vec<u8>>) {
+    line.push('{');
+    let mut names: Vec<&String> = map.keys().collect();
+    names.sort();
+    for (i, name) in names.into_iter().enumerate() {
+        if 0 != i {
+            line.push(',');
+        }
+        string(line, name);
+        line.push(':');
+        string(line, &base64(&map[name]));
+    }
+    line.push('}');
+}
+
+fn entity(line: &mut String, entity: &Option<PosixEntity>) {
+    match entity {
+        Some(entity) => {
+            line.push_str(&format!("{{\"id\":{},\"name\":", entity.id));
+            string(line, &entity.name);
+            line.push('}');
+        }
+        None => line.push_str("null"),
+    }
+}
+
+fn string(line: &mut String, value: &str) {
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c < ' ' => line.push_str(&format!("\\u{:04x}", c as u32)),
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+fn base64(data: &[u8]) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        let mut line = String::new();
+        string(&mut line, "a\"b\\c\n\u{1}");
+        assert_eq!(r#""a\"b\\c\n\u0001""#, line);
+    }
+}
@@ -0,0 +1,101 @@
+/// pax times are decimal seconds, with an optional fraction: "1500000000.123456789".
+/// Like everywhere else, times before 1970 aren't supported.
+pub fn parse_time(value: &[u8]) -> Option<u64> {
+    let value = std::str::from_utf8(value).ok()?;
+    let (seconds, fraction) = match value.find('.') {
+        Some(dot) => (&value[..dot], &value[dot + 1..]),
+        None => (value, ""),
+    };
+
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let seconds: u64 = seconds.parse().ok()?;
+    // only nanosecond precision; pad or truncate to nine digits
+    let nanos = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0u64, |acc, digit| acc * 10 + u64::from(digit - b'0'));
+
+    seconds.checked_mul(1_000_000_000)?.checked_add(nanos)
+}
+
+/// GNU tar writes `SCHILY.xattr.name=value` as-is; libarchive writes
+/// `LIBARCHIVE.xattr.url%20encoded=base64`. (libarchive also writes SCHILY records.)
+pub fn xattr(key: &str, value: &[u8]) -> Option<(String, Vec<u8>)> {
+    if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+        return Some((name.to_string(), value.to_vec()));
+    }
+
+    let name = key.strip_prefix("LIBARCHIVE.xattr.")?;
+    Some((percent_decode(name)?, base64_decode(value)?))
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if b'%' == bytes[i] {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            ret.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            ret.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(ret).ok()
+}
+
+/// Standard alphabet, with or without the padding.
+fn base64_decode(value: &[u8]) -> Option<Vec<u8>> {
+    use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+    use base64::Engine;
+
+    let config = GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true);
+    GeneralPurpose::new(&base64::alphabet::STANDARD, config)
+        .decode(value)
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        assert_eq!(Some(1_500_000_000_000_000_000), parse_time(b"1500000000"));
+        assert_eq!(
+            Some(1_500_000_000_120_000_000),
+            parse_time(b"1500000000.12")
+        );
+        assert_eq!(
+            Some(1_500_000_000_123_456_789),
+            parse_time(b"1500000000.1234567891")
+        );
+        assert_eq!(None, parse_time(b"-1.5"));
+        assert_eq!(None, parse_time(b"1.5x"));
+    }
+
+    #[test]
+    fn xattrs() {
+        assert_eq!(
+            Some(("user.a".to_string(), b"b".to_vec())),
+            xattr("SCHILY.xattr.user.a", b"b")
+        );
+        assert_eq!(
+            Some(("user.a b".to_string(), b"hello".to_vec())),
+            xattr("LIBARCHIVE.xattr.user.a%20b", b"aGVsbG8=")
+        );
+        assert_eq!(
+            Some(("user.a".to_string(), b"hello".to_vec())),
+            xattr("LIBARCHIVE.xattr.user.a", b"aGVsbG8")
+        );
+        assert_eq!(None, xattr("mtime", b"1"));
+    }
+}
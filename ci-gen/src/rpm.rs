@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::tee::skip;
+
+pub const LEAD_MAGIC: &[u8] = &[0xed, 0xab, 0xee, 0xdb];
+const HEADER_MAGIC: &[u8] = &[0x8e, 0xad, 0xe8];
+const LEAD_LEN: usize = 96;
+
+pub const NAME: u32 = 1000;
+pub const VERSION: u32 = 1001;
+pub const RELEASE: u32 = 1002;
+pub const EPOCH: u32 = 1003;
+pub const ARCH: u32 = 1022;
+pub const OLDFILENAMES: u32 = 1027;
+pub const FILEDIGESTS: u32 = 1035;
+pub const FILEUSERNAME: u32 = 1039;
+pub const FILEGROUPNAME: u32 = 1040;
+pub const SOURCERPM: u32 = 1044;
+pub const DIRINDEXES: u32 = 1116;
+pub const BASENAMES: u32 = 1117;
+pub const DIRNAMES: u32 = 1118;
+pub const FILEDIGESTALGO: u32 = 5011;
+
+const TYPE_INT32: u32 = 4;
+const TYPE_STRING: u32 = 6;
+const TYPE_STRING_ARRAY: u32 = 8;
+const TYPE_I18NSTRING: u32 = 9;
+
+struct IndexEntry {
+    type_: u32,
+    offset: usize,
+    count: usize,
+}
+
+/// A header section: the signature, or the actual package header.
+pub struct Header {
+    index: HashMap<u32, IndexEntry>,
+    store: Vec<u8>,
+}
+
+fn invalid<T: AsRef<str>>(msg: T) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.as_ref())
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The lead is a fixed size and entirely obsolete, so just check it looks right.
+pub fn read_lead<R: io::Read>(mut from: R) -> io::Result<()> {
+    let mut lead = [0u8; LEAD_LEN];
+    from.read_exact(&mut lead)?;
+    if LEAD_MAGIC != &lead[..4] {
+        return Err(invalid("invalid rpm lead magic"));
+    }
+
+    // major version
+    match lead[4] {
+        3 | 4 => Ok(()),
+        other => Err(invalid(format!("unsupported rpm version: {}", other))),
+    }
+}
+
+impl Header {
+    /// The signature header is padded to eight bytes; the main header isn't.
+    pub fn read<R: io::Read>(mut from: R, aligned: bool) -> io::Result<Header> {
+        let mut intro = [0u8; 16];
+        from.read_exact(&mut intro)?;
+        if HEADER_MAGIC != &intro[..3] {
+            return Err(invalid("invalid rpm header magic"));
+        }
+
+        let entries = be32(&intro[8..]) as usize;
+        let store_len = be32(&intro[12..]) as usize;
+
+        if entries > 1024 * 1024 || store_len > 256 * 1024 * 1024 {
+            return Err(invalid(format!(
+                "unreasonable rpm header: {} entries, {} bytes",
+                entries, store_len
+            )));
+        }
+
+        let mut raw_index = vec![0u8; entries * 16];
+        from.read_exact(&mut raw_index)?;
+
+        let mut store = vec![0u8; store_len];
+        from.read_exact(&mut store)?;
+
+        if aligned {
+            let len = (raw_index.len() + store.len()) as u64;
+            skip(&mut from, (8 - len % 8) % 8)?;
+        }
+
+        let index = raw_index
+            .chunks(16)
+            .map(|entry| {
+                (
+                    be32(&entry[0..]),
+                    IndexEntry {
+                        type_: be32(&entry[4..]),
+                        offset: be32(&entry[8..]) as usize,
+                        count: be32(&entry[12..]) as usize,
+                    },
+                )
+            })
+            .collect();
+
+        Ok(Header { index, store })
+    }
+
+    pub fn strings(&self, tag: u32) -> Option<Vec<String>> {
+        let entry = self.index.get(&tag)?;
+        let count = match entry.type_ {
+            TYPE_STRING => 1,
+            // translated strings come in every language; the first is the untranslated one
+            TYPE_STRING_ARRAY | TYPE_I18NSTRING => entry.count,
+            _ => return None,
+        };
+
+        let mut ret = Vec::with_capacity(count.min(self.store.len()));
+        let mut pos = entry.offset;
+        for _ in 0..count {
+            let rest = self.store.get(pos..)?;
+            let end = rest.iter().position(|&b| 0 == b)?;
+            ret.push(String::from_utf8_lossy(&rest[..end]).to_string());
+            pos += end + 1;
+        }
+
+        Some(ret)
+    }
+
+    pub fn string(&self, tag: u32) -> Option<String> {
+        self.strings(tag)?.into_iter().next()
+    }
+
+    pub fn int32s(&self, tag: u32) -> Option<Vec<u32>> {
+        let entry = self.index.get(&tag)?;
+        if TYPE_INT32 != entry.type_ {
+            return None;
+        }
+
+        let end = entry.offset.checked_add(entry.count.checked_mul(4)?)?;
+        Some(
+            self.store
+                .get(entry.offset..end)?
+                .chunks(4)
+                .map(be32)
+                .collect(),
+        )
+    }
+
+    /// Every file's full path, in header order, which all the other `FILE*` tags share.
+    pub fn file_names(&self) -> Vec<String> {
+        if let Some(names) = self.strings(OLDFILENAMES) {
+            return names;
+        }
+
+        let (dirs, indexes, bases) = match (
+            self.strings(DIRNAMES),
+            self.int32s(DIRINDEXES),
+            self.strings(BASENAMES),
+        ) {
+            (Some(dirs), Some(indexes), Some(bases)) => (dirs, indexes, bases),
+            _ => return Vec::new(),
+        };
+
+        bases
+            .into_iter()
+            .zip(indexes)
+            .map(|(base, index)| match dirs.get(index as usize) {
+                Some(dir) => format!("{}{}", dir, base),
+                None => base,
+            })
+            .collect()
+    }
+
+    pub fn digest_algorithm(&self) -> &'static str {
+        // https://github.com/rpm-software-management/rpm/blob/master/include/rpm/rpmpgp.h
+        match self.int32s(FILEDIGESTALGO).and_then(|v| v.first().cloned()) {
+            None | Some(1) => "md5",
+            Some(2) => "sha1",
+            Some(8) => "sha256",
+            Some(9) => "sha384",
+            Some(10) => "sha512",
+            Some(11) => "sha224",
+            Some(_) => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        let mut store = b"foo\0/usr/\0/etc/\0a\0b\0c\0\0\0".to_vec();
+        for index in &[0u32, 0, 1] {
+            store.extend_from_slice(&index.to_be_bytes());
+        }
+
+        let index: &[(u32, u32, u32, u32)] = &[
+            (NAME, TYPE_STRING, 0, 1),
+            (DIRNAMES, TYPE_STRING_ARRAY, 4, 2),
+            (BASENAMES, TYPE_STRING_ARRAY, 16, 3),
+            (DIRINDEXES, TYPE_INT32, 24, 3),
+        ];
+
+        let mut data = vec![0x8e, 0xad, 0xe8, 1, 0, 0, 0, 0];
+        data.extend_from_slice(&(index.len() as u32).to_be_bytes());
+        data.extend_from_slice(&(store.len() as u32).to_be_bytes());
+        for &(tag, type_, offset, count) in index {
+            for field in &[tag, type_, offset, count] {
+                data.extend_from_slice(&field.to_be_bytes());
+            }
+        }
+        data.extend_from_slice(&store);
+        data
+    }
+
+    #[test]
+    fn file_names() {
+        let header = Header::read(io::Cursor::new(header()), false).unwrap();
+        assert_eq!(Some("foo".to_string()), header.string(NAME));
+        assert_eq!(None, header.string(VERSION));
+        assert_eq!(Some(vec![0, 0, 1]), header.int32s(DIRINDEXES));
+        assert_eq!(vec!["/usr/a", "/usr/b", "/etc/c"], header.file_names());
+        assert_eq!("md5", header.digest_algorithm());
+    }
+
+    #[test]
+    fn aligned() {
+        let mut data = header();
+        let unpadded = data.len();
+        data.extend_from_slice(&[0u8; 7]);
+        let mut cursor = io::Cursor::new(data);
+        Header::read(&mut cursor, true).unwrap();
+        assert_eq!(0, cursor.position() % 8);
+        assert!(cursor.position() as usize >= unpadded);
+    }
+}
@@ -4,6 +4,7 @@ use std::time;
 
 use anyhow::{Context, Result};
 
+use crate::fat;
 use crate::stat;
 
 pub fn simple_time(dur: time::Duration) -> u64 {
@@ -25,6 +26,27 @@ pub fn simple_time_tm(val: zip::DateTime) -> Result<u64> {
     )))
 }
 
+pub fn simple_time_offset(val: ::time::OffsetDateTime) -> u64 {
+    u64::try_from(val.unix_timestamp_nanos()).unwrap_or(0)
+}
+
+/// FAT has no idea about time zones, so unless exFAT tells us, we pretend it's UTC.
+pub fn simple_time_fat(val: &fat::Timestamp) -> u64 {
+    if 0 == val.date {
+        return 0;
+    }
+
+    let nanos = match simple_time_tm(zip::DateTime::from_msdos(val.date, val.time)) {
+        Ok(nanos) => nanos,
+        Err(_) => return 0,
+    };
+
+    let offset = i64::from(val.offset.unwrap_or(0)) * 60 * 1_000_000_000;
+    (nanos + u64::from(val.centis) * 10_000_000)
+        .checked_add_signed(-offset)
+        .unwrap_or(0)
+}
+
 pub fn simple_time_btime(val: &fs::Metadata) -> Result<u64> {
     match val.created() {
         Ok(time) => Ok(simple_time_sys(time)),
@@ -53,6 +75,15 @@ pub fn simple_time_epoch_seconds(seconds: u64) -> u64 {
     seconds.checked_mul(1_000_000_000).unwrap_or(0)
 }
 
+/// Windows' 100ns ticks since 1601.
+pub fn simple_time_filetime(ticks: u64) -> u64 {
+    const EPOCH_DIFFERENCE: u64 = 116_444_736_000_000_000;
+    ticks
+        .checked_sub(EPOCH_DIFFERENCE)
+        .and_then(|ticks| ticks.checked_mul(100))
+        .unwrap_or(0)
+}
+
 pub fn simple_time_ctime(val: &stat::Stat) -> u64 {
     if val.ctime <= 0 {
         0
@@ -2,6 +2,7 @@ use std::fmt;
 
 use std::rc::Rc;
 
+#[derive(Clone)]
 pub struct SList<T> {
     head: Rc<Node<T>>,
 }
@@ -0,0 +1,238 @@
+use std::io;
+use std::io::Read;
+
+/// How GNU tar describes a sparse member in pax records. Old GNU ('S') members are
+/// reassembled by the `tar` crate itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sparse {
+    /// The logical size of the file, holes included.
+    pub size: u64,
+    /// (offset, length) of each data region; `None` for 1.0, where it starts the data.
+    pub map: Option<Vec<(u64, u64)>>,
+}
+
+fn invalid<T: AsRef<str>>(msg: T) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.as_ref())
+}
+
+fn number(value: &[u8]) -> io::Result<u64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| invalid(format!("invalid sparse number: {:?}", value)))
+}
+
+fn pairs(numbers: Vec<u64>) -> io::Result<Vec<(u64, u64)>> {
+    if !numbers.len().is_multiple_of(2) {
+        return Err(invalid("odd number of values in sparse map"));
+    }
+    Ok(numbers.chunks_exact(2).map(|c| (c[0], c[1])).collect())
+}
+
+impl Sparse {
+    /// Records in archive order, as 0.0 repeats `GNU.sparse.offset` and `GNU.sparse.numbytes`.
+    pub fn from_pax(records: &[(String, Vec<u8>)]) -> io::Result<Option<Sparse>> {
+        let get = |key: &str| {
+            records
+                .iter()
+                .rev()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_slice())
+        };
+
+        if let (Some(b"1"), Some(size)) = (get("GNU.sparse.major"), get("GNU.sparse.realsize")) {
+            return Ok(Some(Sparse {
+                size: number(size)?,
+                map: None,
+            }));
+        }
+
+        let size = match get("GNU.sparse.size") {
+            Some(size) => number(size)?,
+            None => return Ok(None),
+        };
+
+        let map = match get("GNU.sparse.map") {
+            // 0.1
+            Some(map) => pairs(
+                map.split(|&b| b',' == b)
+                    .filter(|v| !v.is_empty())
+                    .map(number)
+                    .collect::<io::Result<_>>()?,
+            )?,
+            // 0.0
+            None => pairs(
+                records
+                    .iter()
+                    .filter(|(k, _)| k == "GNU.sparse.offset" || k == "GNU.sparse.numbytes")
+                    .map(|(_, v)| number(v))
+                    .collect::<io::Result<_>>()?,
+            )?,
+        };
+
+        Ok(Some(Sparse {
+            size,
+            map: Some(map),
+        }))
+    }
+
+    /// The map as GNU tar writes it in 0.1: "offset,length,offset,length".
+    pub fn describe(map: &[(u64, u64)]) -> String {
+        map.iter()
+            .map(|(offset, len)| format!("{},{}", offset, len))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// The 1.0 map: decimal numbers, one per line, a count then pairs, padded to a 512-byte block.
+pub fn read_map<R: Read>(mut from: R) -> io::Result<Vec<(u64, u64)>> {
+    let mut consumed = 0u64;
+    let mut line = || -> io::Result<u64> {
+        let mut value = Vec::new();
+        loop {
+            let mut byte = [0u8];
+            from.read_exact(&mut byte)?;
+            consumed += 1;
+            if b'\n' == byte[0] {
+                return number(&value);
+            }
+            if value.len() > 20 {
+                return Err(invalid("sparse map line too long"));
+            }
+            value.push(byte[0]);
+        }
+    };
+
+    let count = line()?;
+    let count = count
+        .checked_mul(2)
+        .ok_or_else(|| invalid("sparse map too long"))?;
+    let mut numbers = Vec::new();
+    for _ in 0..count {
+        numbers.push(line()?);
+    }
+
+    let padding = (512 - consumed % 512) % 512;
+    io::copy(&mut (&mut from).take(padding), &mut io::sink())?;
+    pairs(numbers)
+}
+
+/// The logical contents: the data regions from `inner`, with zeros in the holes between.
+pub struct SparseReader<R> {
+    inner: R,
+    map: std::vec::IntoIter<(u64, u64)>,
+    /// The end of the current region, or of the file, and where the data in it starts.
+    region: (u64, u64),
+    pos: u64,
+    size: u64,
+}
+
+impl<R: Read> SparseReader<R> {
+    pub fn new(inner: R, map: Vec<(u64, u64)>, size: u64) -> io::Result<SparseReader<R>> {
+        let mut end = 0u64;
+        for &(offset, len) in &map {
+            if offset < end {
+                return Err(invalid("overlapping or out of order sparse regions"));
+            }
+            end = offset
+                .checked_add(len)
+                .filter(|&end| end <= size)
+                .ok_or_else(|| invalid("sparse region past the end of the file"))?;
+        }
+
+        Ok(SparseReader {
+            inner,
+            map: map.into_iter(),
+            region: (0, 0),
+            pos: 0,
+            size,
+        })
+    }
+}
+
+impl<R: Read> Read for SparseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.region.0 {
+            if self.pos == self.size {
+                return Ok(0);
+            }
+            self.region = match self.map.next() {
+                Some((offset, len)) => (offset + len, offset),
+                None => (self.size, self.size),
+            };
+        }
+
+        let (end, data) = self.region;
+        if self.pos < data {
+            let len = buf.len().min((data - self.pos) as usize);
+            buf[..len].iter_mut().for_each(|b| *b = 0);
+            self.pos += len as u64;
+            return Ok(len);
+        }
+
+        let len = buf.len().min((end - self.pos) as usize);
+        let found = self.inner.read(&mut buf[..len])?;
+        if 0 == found && 0 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.pos += found as u64;
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(list: &[(&str, &str)]) -> Vec<(String, Vec<u8>)> {
+        list.iter()
+            .map(|(k, v)| (k.to_string(), v.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn maps() {
+        let expected = Some(Sparse {
+            size: 20,
+            map: Some(vec![(2, 3), (10, 1)]),
+        });
+        let old = records(&[
+            ("GNU.sparse.size", "20"),
+            ("GNU.sparse.numblocks", "2"),
+            ("GNU.sparse.offset", "2"),
+            ("GNU.sparse.numbytes", "3"),
+            ("GNU.sparse.offset", "10"),
+            ("GNU.sparse.numbytes", "1"),
+        ]);
+        assert_eq!(expected, Sparse::from_pax(&old).unwrap());
+
+        let new = records(&[("GNU.sparse.size", "20"), ("GNU.sparse.map", "2,3,10,1")]);
+        assert_eq!(expected, Sparse::from_pax(&new).unwrap());
+
+        let mut data = b"2\n2\n3\n10\n1\n".to_vec();
+        data.resize(512, 0);
+        data.extend_from_slice(b"abcd");
+        let mut data = &data[..];
+        assert_eq!(vec![(2, 3), (10, 1)], read_map(&mut data).unwrap());
+        assert_eq!(b"abcd", data);
+
+        assert_eq!(None, Sparse::from_pax(&records(&[("mtime", "1")])).unwrap());
+    }
+
+    #[test]
+    fn holes() {
+        let mut out = Vec::new();
+        SparseReader::new(&b"abcd"[..], vec![(2, 3), (10, 1)], 12)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(b"\0\0abc\0\0\0\0\0d\0", &out[..]);
+
+        assert!(SparseReader::new(&b""[..], vec![(2, 3), (1, 1)], 12).is_err());
+        assert!(SparseReader::new(&b"ab"[..], vec![(2, 3)], 12)
+            .unwrap()
+            .read_to_end(&mut out)
+            .is_err());
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+pub const MAGIC: &[u8] = b"hsqs";
+
+/// Metadata (inodes, directories, tables) is stored in blocks of at most this, uncompressed.
+const METADATA_BLOCK: usize = 8192;
+const NO_FRAGMENT: u32 = 0xffff_ffff;
+const NO_XATTRS: u32 = 0xffff_ffff;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Lzma,
+    Lzo,
+    Xz,
+    Lz4,
+    Zstd,
+    Unknown(u16),
+}
+
+impl Compression {
+    pub fn is_supported(self) -> bool {
+        !matches!(self, Compression::Lzo | Compression::Unknown(_))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct FileData {
+    blocks_start: u64,
+    size: u64,
+    /// The top bits say if the block is stored uncompressed; zero means a sparse block.
+    block_sizes: Vec<u32>,
+    /// The tail of the file, packed in with the tails of other files: (index, offset).
+    fragment: Option<(u32, u32)>,
+}
+
+/// Where a directory's children are: (block, offset in block, size)
+type Listing = (u64, usize, u32);
+
+#[derive(Clone, Debug, Default)]
+pub struct Entry {
+    pub path: String,
+    pub inode_number: u32,
+    /// Including the file type bits, which are really stored in the inode type.
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u32,
+    pub device: (u32, u32),
+    pub link: Option<String>,
+    pub xattrs: HashMap<String, Vec<u8>>,
+    data: Option<FileData>,
+}
+
+pub struct SquashFs<R> {
+    inner: R,
+    compression: Compression,
+    block_size: u32,
+    root: u64,
+    inode_table: u64,
+    directory_table: u64,
+    xattr_table: Option<u64>,
+    ids: Vec<u32>,
+    fragments: Vec<(u64, u32)>,
+    xattr_ids: Vec<(u64, u32)>,
+    /// Decompressed metadata blocks, and where the next one starts.
+    metadata: HashMap<u64, (Vec<u8>, u64)>,
+    fragment_cache: Option<(u32, Vec<u8>)>,
+}
+
+fn invalid<T: AsRef<str>>(msg: T) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.as_ref())
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    u64::from(le32(bytes)) | u64::from(le32(&bytes[4..])) << 32
+}
+
+/// Linux's "new" encoding of a `dev_t` in 32-bits.
+fn split_dev(dev: u32) -> (u32, u32) {
+    ((dev >> 8) & 0xfff, (dev & 0xff) | ((dev >> 12) & 0xfff00))
+}
+
+fn decompress(compression: Compression, data: &[u8], max: usize) -> io::Result<Vec<u8>> {
+    let mut ret = Vec::with_capacity(max);
+    match compression {
+        // it's actually zlib
+        Compression::Gzip => {
+            libflate::zlib::Decoder::new(data)?
+                .take(max as u64)
+                .read_to_end(&mut ret)?;
+        }
+        Compression::Lzma => {
+            let stream = xz2::stream::Stream::new_lzma_decoder(u64::MAX)?;
+            xz2::read::XzDecoder::new_stream(data, stream)
+                .take(max as u64)
+                .read_to_end(&mut ret)?;
+        }
+        Compression::Xz => {
+            xz2::read::XzDecoder::new(data)
+                .take(max as u64)
+                .read_to_end(&mut ret)?;
+        }
+        Compression::Lz4 => {
+            ret.resize(max, 0);
+            let found = lz4::block::decompress_to_buffer(data, Some(max as i32), &mut ret)?;
+            ret.truncate(found);
+        }
+        Compression::Zstd => ret = zstd::bulk::decompress(data, max)?,
+        Compression::Lzo | Compression::Unknown(_) => {
+            return Err(invalid(format!(
+                "unsupported compression: {:?}",
+                compression
+            )))
+        }
+    }
+    Ok(ret)
+}
+
+impl<R: Read + Seek> SquashFs<R> {
+    pub fn open(mut inner: R) -> io::Result<SquashFs<R>> {
+        let mut sb = [0u8; 96];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut sb)?;
+
+        if MAGIC != &sb[..4] {
+            return Err(invalid("invalid squashfs magic"));
+        }
+
+        let version = (le16(&sb[28..]), le16(&sb[30..]));
+        if (4, 0) != version {
+            return Err(invalid(format!(
+                "unsupported squashfs version: {:?}",
+                version
+            )));
+        }
+
+        let block_size = le32(&sb[12..]);
+        if !(4096..=1024 * 1024).contains(&block_size) {
+            return Err(invalid(format!("unreasonable block size: {}", block_size)));
+        }
+
+        let compression = match le16(&sb[20..]) {
+            1 => Compression::Gzip,
+            2 => Compression::Lzma,
+            3 => Compression::Lzo,
+            4 => Compression::Xz,
+            5 => Compression::Lz4,
+            6 => Compression::Zstd,
+            other => Compression::Unknown(other),
+        };
+
+        let xattr_table = le64(&sb[56..]);
+
+        let mut fs = SquashFs {
+            inner,
+            compression,
+            block_size,
+            root: le64(&sb[32..]),
+            inode_table: le64(&sb[64..]),
+            directory_table: le64(&sb[72..]),
+            xattr_table: None,
+            ids: Vec::new(),
+            fragments: Vec::new(),
+            xattr_ids: Vec::new(),
+            metadata: HashMap::new(),
+            fragment_cache: None,
+        };
+
+        if !compression.is_supported() {
+            return Ok(fs);
+        }
+
+        fs.ids = fs
+            .read_table(le64(&sb[48..]), le16(&sb[26..]) as usize, 4)?
+            .chunks(4)
+            .map(le32)
+            .collect();
+
+        fs.fragments = fs
+            .read_table(le64(&sb[80..]), le32(&sb[16..]) as usize, 16)?
+            .chunks(16)
+            .map(|entry| (le64(entry), le32(&entry[8..])))
+            .collect();
+
+        if u64::MAX != xattr_table {
+            let mut header = [0u8; 16];
+            fs.inner.seek(SeekFrom::Start(xattr_table))?;
+            fs.inner.read_exact(&mut header)?;
+            fs.xattr_table = Some(le64(&header));
+            fs.xattr_ids = fs
+                .read_table(xattr_table + 16, le32(&header[8..]) as usize, 16)?
+                .chunks(16)
+                .map(|entry| (le64(entry), le32(&entry[8..])))
+                .collect();
+        }
+
+        Ok(fs)
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// The lookup tables are a list of pointers to metadata blocks, which follow each other.
+    fn read_table(&mut self, start: u64, count: usize, size: usize) -> io::Result<Vec<u8>> {
+        if 0 == count {
+            return Ok(Vec::new());
+        }
+
+        if count > 16 * 1024 * 1024 {
+            return Err(invalid(format!("unreasonable table size: {}", count)));
+        }
+
+        let mut first = [0u8; 8];
+        self.inner.seek(SeekFrom::Start(start))?;
+        self.inner.read_exact(&mut first)?;
+        self.read_metadata(&mut (le64(&first), 0), count * size)
+    }
+
+    fn metadata_block(&mut self, pos: u64) -> io::Result<&(Vec<u8>, u64)> {
+        if !self.metadata.contains_key(&pos) {
+            let mut header = [0u8; 2];
+            self.inner.seek(SeekFrom::Start(pos))?;
+            self.inner.read_exact(&mut header)?;
+            let header = le16(&header);
+            let len = usize::from(header & 0x7fff);
+
+            let mut data = vec![0u8; len];
+            self.inner.read_exact(&mut data)?;
+
+            if 0 == header & 0x8000 {
+                data = decompress(self.compression, &data, METADATA_BLOCK)?;
+            }
+
+            // everything is normally read in order, so there's little point keeping much around
+            if self.metadata.len() > 1024 {
+                self.metadata.clear();
+            }
+
+            self.metadata.insert(pos, (data, pos + 2 + len as u64));
+        }
+
+        Ok(&self.metadata[&pos])
+    }
+
+    /// Read from metadata, at (block position, offset in the uncompressed block), moving it on.
+    fn read_metadata(&mut self, at: &mut (u64, usize), len: usize) -> io::Result<Vec<u8>> {
+        let mut ret = Vec::with_capacity(len.min(METADATA_BLOCK));
+        while ret.len() < len {
+            let (data, next) = self.metadata_block(at.0)?;
+            let available = data.get(at.1..).unwrap_or_default();
+            if available.is_empty() {
+                if data.is_empty() {
+                    return Err(invalid("empty metadata block"));
+                }
+                *at = (*next, 0);
+                continue;
+            }
+
+            let wanted = available.len().min(len - ret.len());
+            ret.extend_from_slice(&available[..wanted]);
+            at.1 += wanted;
+        }
+
+        Ok(ret)
+    }
+
+    fn id(&self, index: u16) -> io::Result<u32> {
+        self.ids
+            .get(usize::from(index))
+            .cloned()
+            .ok_or_else(|| invalid(format!("invalid id index: {}", index)))
+    }
+
+    fn inode(&mut self, reference: u64, path: String) -> io::Result<(Entry, Option<Listing>)> {
+        let mut at = (
+            self.inode_table + (reference >> 16),
+            (reference & 0xffff) as usize,
+        );
+
+        let header = self.read_metadata(&mut at, 16)?;
+        let kind = le16(&header);
+        let mut entry = Entry {
+            path,
+            mode: u32::from(le16(&header[2..]) & 0o7777),
+            uid: self.id(le16(&header[4..]))?,
+            gid: self.id(le16(&header[6..]))?,
+            mtime: le32(&header[8..]),
+            inode_number: le32(&header[12..]),
+            ..Entry::default()
+        };
+
+        let mut directory = None;
+        let mut xattr = NO_XATTRS;
+
+        match kind {
+            // directory
+            1 => {
+                let d = self.read_metadata(&mut at, 16)?;
+                entry.mode |= 0o040000;
+                entry.nlink = le32(&d[4..]);
+                directory = Some((
+                    u64::from(le32(&d)),
+                    usize::from(le16(&d[10..])),
+                    u32::from(le16(&d[8..])),
+                ));
+            }
+            8 => {
+                let d = self.read_metadata(&mut at, 24)?;
+                entry.mode |= 0o040000;
+                entry.nlink = le32(&d);
+                directory = Some((
+                    u64::from(le32(&d[8..])),
+                    usize::from(le16(&d[18..])),
+                    le32(&d[4..]),
+                ));
+                xattr = le32(&d[20..]);
+            }
+
+            // regular file
+            2 | 9 => {
+                let (blocks_start, size, fragment, offset) = if 2 == kind {
+                    let d = self.read_metadata(&mut at, 16)?;
+                    entry.nlink = 1;
+                    (
+                        u64::from(le32(&d)),
+                        u64::from(le32(&d[12..])),
+                        le32(&d[4..]),
+                        le32(&d[8..]),
+                    )
+                } else {
+                    let d = self.read_metadata(&mut at, 40)?;
+                    entry.nlink = le32(&d[24..]);
+                    xattr = le32(&d[36..]);
+                    (le64(&d), le64(&d[8..]), le32(&d[28..]), le32(&d[32..]))
+                };
+
+                let block_size = u64::from(self.block_size);
+                let mut blocks = size / block_size;
+                if NO_FRAGMENT == fragment && 0 != size % block_size {
+                    blocks += 1;
+                }
+
+                let block_sizes = self
+                    .read_metadata(&mut at, blocks as usize * 4)?
+                    .chunks(4)
+                    .map(le32)
+                    .collect();
+
+                entry.mode |= 0o100000;
+                entry.data = Some(FileData {
+                    blocks_start,
+                    size,
+                    block_sizes,
+                    fragment: if NO_FRAGMENT == fragment {
+                        None
+                    } else {
+                        Some((fragment, offset))
+                    },
+                });
+            }
+
+            3 | 10 => {
+                let d = self.read_metadata(&mut at, 8)?;
+                entry.mode |= 0o120000;
+                entry.nlink = le32(&d);
+                let target_len = le32(&d[4..]) as usize;
+                if target_len > 64 * 1024 {
+                    return Err(invalid(format!(
+                        "unreasonable symlink length: {}",
+                        target_len
+                    )));
+                }
+                let target = self.read_metadata(&mut at, target_len)?;
+                entry.link = Some(String::from_utf8_lossy(&target).to_string());
+                if 10 == kind {
+                    xattr = le32(&self.read_metadata(&mut at, 4)?);
+                }
+            }
+
+            // block and character devices
+            4 | 5 | 11 | 12 => {
+                let d = self.read_metadata(&mut at, if kind > 7 { 12 } else { 8 })?;
+                entry.mode |= if 4 == kind % 7 { 0o060000 } else { 0o020000 };
+                entry.nlink = le32(&d);
+                entry.device = split_dev(le32(&d[4..]));
+                if kind > 7 {
+                    xattr = le32(&d[8..]);
+                }
+            }
+
+            // fifos and sockets
+            6 | 7 | 13 | 14 => {
+                let d = self.read_metadata(&mut at, if kind > 7 { 8 } else { 4 })?;
+                entry.mode |= if 6 == kind % 7 { 0o010000 } else { 0o140000 };
+                entry.nlink = le32(&d);
+                if kind > 7 {
+                    xattr = le32(&d[4..]);
+                }
+            }
+
+            other => return Err(invalid(format!("invalid inode type: {}", other))),
+        }
+
+        if NO_XATTRS != xattr {
+            entry.xattrs = self.xattrs(xattr)?;
+        }
+
+        Ok((entry, directory))
+    }
+
+    fn xattrs(&mut self, index: u32) -> io::Result<HashMap<String, Vec<u8>>> {
+        let (reference, count) = *self
+            .xattr_ids
+            .get(index as usize)
+            .ok_or_else(|| invalid(format!("invalid xattr index: {}", index)))?;
+        let table = self
+            .xattr_table
+            .ok_or_else(|| invalid("xattrs without a table"))?;
+
+        let mut ret = HashMap::new();
+        let mut at = (table + (reference >> 16), (reference & 0xffff) as usize);
+        for _ in 0..count {
+            let key = self.read_metadata(&mut at, 4)?;
+            let kind = le16(&key);
+            let name = self.read_metadata(&mut at, usize::from(le16(&key[2..])))?;
+            let prefix = match kind & 0xff {
+                0 => "user.",
+                1 => "trusted.",
+                2 => "security.",
+                other => return Err(invalid(format!("invalid xattr prefix: {}", other))),
+            };
+
+            let len = le32(&self.read_metadata(&mut at, 4)?) as usize;
+            let mut value = self.read_metadata(&mut at, len.min(64 * 1024))?;
+
+            // large values can be shared, and stored elsewhere
+            if 0 != kind & 0x100 {
+                let reference = le64(&value);
+                let mut elsewhere = (table + (reference >> 16), (reference & 0xffff) as usize);
+                let len = le32(&self.read_metadata(&mut elsewhere, 4)?) as usize;
+                value = self.read_metadata(&mut elsewhere, len.min(64 * 1024))?;
+            }
+
+            ret.insert(
+                format!("{}{}", prefix, String::from_utf8_lossy(&name)),
+                value,
+            );
+        }
+
+        Ok(ret)
+    }
+
+    /// Every inode, parents before their children.
+    pub fn entries(&mut self) -> io::Result<Vec<Entry>> {
+        let mut ret = Vec::new();
+        let mut seen = HashSet::new();
+
+        let (_, root) = self.inode(self.root, String::new())?;
+        let mut todo = vec![(String::new(), root)];
+
+        while let Some((prefix, dir)) = todo.pop() {
+            let (block, offset, size) = match dir {
+                Some(dir) => dir,
+                None => continue,
+            };
+
+            // a corrupt (or malicious) image can have loops
+            if !seen.insert((block, offset)) {
+                continue;
+            }
+
+            let mut at = (self.directory_table + block, offset);
+            // the size includes the "." and ".." entries that aren't stored
+            let mut remaining = size.saturating_sub(3) as usize;
+
+            while remaining >= 12 {
+                let header = self.read_metadata(&mut at, 12)?;
+                remaining -= 12;
+                let start = u64::from(le32(&header[4..]));
+
+                for _ in 0..=le32(&header) {
+                    let item = self.read_metadata(&mut at, 8)?;
+                    let name_len = usize::from(le16(&item[6..])) + 1;
+                    let name = self.read_metadata(&mut at, name_len)?;
+                    remaining = remaining.saturating_sub(8 + name_len);
+
+                    let path = format!("{}{}", prefix, String::from_utf8_lossy(&name));
+                    let (entry, child) = self.inode(start << 16 | u64::from(le16(&item)), path)?;
+                    if child.is_some() {
+                        todo.push((format!("{}/", entry.path), child));
+                    }
+                    ret.push(entry);
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn open_file<'s>(&'s mut self, entry: &Entry) -> impl Read + 's {
+        FileReader {
+            fs: self,
+            data: entry.data.clone(),
+            pos: entry.data.as_ref().map_or(0, |d| d.blocks_start),
+            block: 0,
+            done: 0,
+            buf: Vec::new(),
+            buf_pos: 0,
+        }
+    }
+
+    fn fragment(&mut self, index: u32) -> io::Result<&[u8]> {
+        if self.fragment_cache.as_ref().map(|(i, _)| *i) != Some(index) {
+            let (start, size) = *self
+                .fragments
+                .get(index as usize)
+                .ok_or_else(|| invalid(format!("invalid fragment index: {}", index)))?;
+            let data = self.data_block(start, size, self.block_size as usize)?;
+            self.fragment_cache = Some((index, data));
+        }
+
+        Ok(&self.fragment_cache.as_ref().expect("just populated").1)
+    }
+
+    fn data_block(&mut self, pos: u64, size: u32, expected: usize) -> io::Result<Vec<u8>> {
+        let len = (size & 0xff_ffff) as usize;
+        if 0 == len {
+            return Ok(vec![0u8; expected]);
+        }
+
+        if len > self.block_size as usize * 2 {
+            return Err(invalid(format!("unreasonable block length: {}", len)));
+        }
+
+        let mut data = vec![0u8; len];
+        self.inner.seek(SeekFrom::Start(pos))?;
+        self.inner.read_exact(&mut data)?;
+
+        if 0 == size & 0x100_0000 {
+            data = decompress(self.compression, &data, self.block_size as usize)?;
+        }
+
+        Ok(data)
+    }
+}
+
+struct FileReader<'s, R> {
+    fs: &'s mut SquashFs<R>,
+    data: Option<FileData>,
+    /// Where the next block is on disk.
+    pos: u64,
+    block: usize,
+    /// How much of the file has been loaded into `buf` so far.
+    done: u64,
+    buf: Vec<u8>,
+    buf_pos: usize,
+}
+
+impl<'s, R: Read + Seek> FileReader<'s, R> {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let data = match self.data.as_ref() {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let wanted = (data.size - self.done).min(u64::from(self.fs.block_size)) as usize;
+        if 0 == wanted {
+            return Ok(None);
+        }
+
+        let mut chunk = if let Some(&size) = data.block_sizes.get(self.block) {
+            self.block += 1;
+            let chunk = self.fs.data_block(self.pos, size, wanted)?;
+            self.pos += u64::from(size & 0xff_ffff);
+            chunk
+        } else if let Some((index, offset)) = data.fragment {
+            let fragment = self.fs.fragment(index)?;
+            fragment
+                .get(offset as usize..offset as usize + wanted)
+                .ok_or_else(|| invalid("fragment too short"))?
+                .to_vec()
+        } else {
+            return Err(invalid("file data ended early"));
+        };
+
+        if chunk.len() < wanted {
+            return Err(invalid("block too short"));
+        }
+        chunk.truncate(wanted);
+        self.done += wanted as u64;
+
+        Ok(Some(chunk))
+    }
+}
+
+impl<'s, R: Read + Seek> Read for FileReader<'s, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf_pos == self.buf.len() {
+            match self.next_chunk()? {
+                Some(chunk) => {
+                    self.buf = chunk;
+                    self.buf_pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let available = &self.buf[self.buf_pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.buf_pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices() {
+        assert_eq!((1, 3), split_dev(0x103));
+        assert_eq!((8, 0x123), split_dev(0x10_0823));
+    }
+}
@@ -8,6 +8,10 @@ pub struct Stat {
     pub ctime: i64,
     pub ctime_nano: i64,
     pub mode: u32,
+    pub rdev: u64,
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
 }
 
 impl Stat {
@@ -20,6 +24,10 @@ impl Stat {
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }
     }
 
@@ -32,6 +40,10 @@ impl Stat {
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }
     }
 
@@ -44,6 +56,10 @@ impl Stat {
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }
     }
 
@@ -56,6 +72,10 @@ impl Stat {
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }
     }
 
@@ -68,6 +88,10 @@ impl Stat {
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }
     }
 
@@ -80,6 +104,10 @@ impl Stat {
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }
     }
 
@@ -92,6 +120,10 @@ impl Stat {
             ctime: meta.st_ctime(),
             ctime_nano: meta.st_ctime_nsec(),
             mode: meta.st_mode(),
+            rdev: meta.st_rdev(),
+            dev: meta.st_dev(),
+            ino: meta.st_ino(),
+            nlink: meta.st_nlink(),
         }
     }
 
@@ -111,6 +143,10 @@ impl Stat {
             ctime: 0,
             ctime_nano: 0,
             mode: 0,
+            rdev: 0,
+            dev: 0,
+            ino: 0,
+            nlink: 0,
         }
     }
 }
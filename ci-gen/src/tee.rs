@@ -13,7 +13,9 @@ use crate::unpacker::Unpacker;
 pub trait Tee: io::BufRead {
     fn reset(&mut self) -> Result<()>;
     fn len_and_reset(&mut self) -> Result<u64>;
-    fn as_seekable(&mut self) -> Result<&mut dyn Seeker>;
+    fn as_seekable(&mut self, log: &Unpacker) -> Result<&mut dyn Seeker>;
+    /// `false` if `as_seekable` would have to copy everything out first.
+    fn is_seekable(&self) -> bool;
 }
 
 pub struct TempFileTee {
@@ -37,11 +39,41 @@ pub fn read_all<R: io::Read>(mut reader: R, buf: &mut [u8]) -> io::Result<usize>
     }
 }
 
+pub fn skip<R: io::Read>(from: R, len: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut from.take(len), &mut io::sink())?;
+    if skipped != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Like `io::copy`, but charging the unpacker for every byte, so we stop at its limits.
+fn copy_limited<R: io::Read, W: io::Write>(
+    mut from: R,
+    mut to: W,
+    mut so_far: u64,
+    log: &Unpacker,
+) -> Result<u64> {
+    let mut buf = [0u8; 8 * 1024];
+    loop {
+        let read = match from.read(&mut buf) {
+            Ok(0) => return Ok(so_far),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        log.charge(so_far, read as u64)?;
+        to.write_all(&buf[..read])?;
+        so_far += read as u64;
+    }
+}
+
 impl TempFileTee {
     pub fn if_necessary<U: io::Read>(mut from: U, log: &Unpacker) -> Result<Box<dyn Tee>> {
         const MEM_LIMIT: usize = 32 * 1024;
         let mut buf = [0u8; MEM_LIMIT];
         let read = read_all(&mut from, &mut buf)?;
+        log.charge(0, read as u64)?;
         if read < MEM_LIMIT {
             return Ok(Box::new(BufReaderTee::new(io::Cursor::new(
                 buf[..read].to_vec(),
@@ -53,12 +85,9 @@ impl TempFileTee {
         {
             let mut writer = io::BufWriter::new(&tmp);
             writer.write_all(&buf)?;
-            let written = io::copy(&mut from, &mut writer)?;
+            let written = copy_limited(&mut from, &mut writer, MEM_LIMIT as u64, log)?;
             log.log(3, || {
-                format!(
-                    "file spills to temp file: {}kB",
-                    (MEM_LIMIT as u64 + written) / 1024
-                )
+                format!("file spills to temp file: {}kB", written / 1024)
             })?;
         }
 
@@ -68,6 +97,23 @@ impl TempFileTee {
             inner: io::BufReader::new(tmp),
         }))
     }
+
+    /// Always to disk, and not charged: for content which is only passing through.
+    pub fn uncharged<U: io::Read>(mut from: U) -> Result<Box<dyn Tee>> {
+        let mut tmp = tempfile()?;
+
+        {
+            let mut writer = io::BufWriter::new(&tmp);
+            io::copy(&mut from, &mut writer)?;
+            writer.flush()?;
+        }
+
+        tmp.seek(BEGINNING)?;
+
+        Ok(Box::new(TempFileTee {
+            inner: io::BufReader::new(tmp),
+        }))
+    }
 }
 
 const BEGINNING: io::SeekFrom = io::SeekFrom::Start(0);
@@ -87,9 +133,13 @@ impl Tee for TempFileTee {
         Ok(len)
     }
 
-    fn as_seekable(&mut self) -> Result<&mut dyn Seeker> {
+    fn as_seekable(&mut self, _log: &Unpacker) -> Result<&mut dyn Seeker> {
         Ok(&mut self.inner)
     }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
 }
 
 // Look, I didn't want to implement these. I wanted to return the implementation.
@@ -139,9 +189,13 @@ where
         Ok(len)
     }
 
-    fn as_seekable(&mut self) -> Result<&mut dyn Seeker> {
+    fn as_seekable(&mut self, _log: &Unpacker) -> Result<&mut dyn Seeker> {
         Ok(&mut *self.inner)
     }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
 }
 
 impl<R: io::Read> io::Read for BufReaderTee<R> {
@@ -190,11 +244,11 @@ where
         ))
     }
 
-    fn as_seekable(&mut self) -> Result<&mut dyn Seeker> {
+    fn as_seekable(&mut self, log: &Unpacker) -> Result<&mut dyn Seeker> {
         let mut temp = tempfile()?;
         {
             let mut fd = io::BufWriter::new(&mut temp);
-            io::copy(self, &mut fd)?;
+            copy_limited(&mut *self, &mut fd, 0, log)?;
         }
 
         temp.seek(io::SeekFrom::Start(0))?;
@@ -203,6 +257,10 @@ where
         self.temp = Some(reader);
         Ok(self.temp.as_mut().unwrap())
     }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
 }
 
 impl<T> io::Read for FailingTee<T>
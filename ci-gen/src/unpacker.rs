@@ -1,21 +1,40 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fmt;
 use std::fs;
 use std::io;
+use std::io::Read;
 use std::io::Seek;
 use std::io::Write;
 use std::path;
 
 use anyhow::{anyhow, bail, Context, Result};
 
+use crate::cpio;
+use crate::digest;
+use crate::fat;
 use crate::gzip;
+use crate::iso9660;
+use crate::limits::Usage;
+use crate::name::Name;
 use crate::output_capnp;
+use crate::output_json;
+use crate::pax;
+use crate::rpm;
+use crate::sevenz;
+use crate::sparse;
+use crate::squashfs;
+use crate::zip_extra;
 
 use crate::errors::*;
 use crate::simple_time::*;
 use crate::tee::*;
 
+use crate::ArchiveReadFailure;
 use crate::Options;
+use crate::OutputFormat;
 
 use ci_capnp::ItemType;
 use ci_capnp::Meta;
@@ -25,9 +44,29 @@ use crate::filetype::FileType;
 use crate::errors::ErrorKind;
 use crate::slist::SList;
 
+struct CpioState<'d> {
+    /// Hardlinks are only identified by sharing an inode number.
+    /// This maps (device, inode) to the path which was emitted with the content.
+    seen: HashMap<(u64, u64), String>,
+    /// links whose content hasn't arrived yet; newc only stores it with the last link
+    pending: BTreeMap<(u64, u64), Vec<(Name, Meta)>>,
+    /// extra metadata for each entry, e.g. from an rpm's header
+    decorate: &'d dyn Fn(&str, &mut Meta),
+}
+
 pub struct Unpacker<'a> {
     options: &'a Options,
+    /// How many entries have been written for this input so far, and what was buffered.
+    usage: &'a Usage,
     current: crate::EntryBuilder,
+    /// This, or a container it's in, matched an `--include`.
+    included: bool,
+    /// The container this came out of, whose record has to come before this.
+    parent: Option<&'a Unpacker<'a>>,
+    /// Our `format_fields` have gone out, on a record, as we're a container.
+    recorded: Cell<bool>,
+    /// What the format said about this container, as a whole, and hasn't been recorded yet.
+    notes: RefCell<HashMap<String, Vec<u8>>>,
 }
 
 impl<'a> Unpacker<'a> {
@@ -54,19 +93,128 @@ impl<'a> Unpacker<'a> {
 
     fn complete(&self, mut file: Box<dyn Tee>) -> Result<()> {
         let size = file.len_and_reset()?;
-        self.complete_details(file, size)
+        if !self.hashing() {
+            return self.complete_details(file, size);
+        }
+
+        // it's already buffered, so can just be read twice
+        let digests = digest::digest(&self.options.digests, &mut file)?;
+        file.reset()?;
+        self.emit(file, size, &digests)
+    }
+
+    fn complete_details<R: io::Read>(&self, src: R, size: u64) -> Result<()> {
+        if !self.hashing() {
+            return self.emit(src, size, &[]);
+        }
+
+        if !self.options.content_output {
+            let digests = digest::digest(&self.options.digests, src)?;
+            return self.emit(io::empty(), size, &digests);
+        }
+
+        // the digests go in the header, which has to be written before the content;
+        // anything which needed charging for has been already
+        let mut hashing = digest::Hashing::new(&self.options.digests, src);
+        let tee = TempFileTee::uncharged(&mut hashing)?;
+        let digests = hashing.finish();
+        self.emit(tee, size, &digests)
+    }
+
+    /// Only files have content worth hashing, and only if they're going to be emitted.
+    fn hashing(&self) -> bool {
+        !self.options.digests.is_empty()
+            && matches!(
+                self.current.meta.item_type,
+                ItemType::RegularFile | ItemType::Unknown
+            )
+            && self.included
+            && !self.options.filter.excludes(&self.current.path)
+    }
+
+    fn emit<R: io::Read>(
+        &self,
+        mut src: R,
+        size: u64,
+        digests: &[(String, Vec<u8>)],
+    ) -> Result<()> {
+        if !self.included || self.options.filter.excludes(&self.current.path) {
+            io::copy(&mut src, &mut io::sink())?;
+            return Ok(());
+        }
+
+        if let Some(parent) = self.parent {
+            parent.record()?;
+        }
+
+        self.write(&self.current, src, size, digests)
     }
 
-    fn complete_details<R: io::Read>(&self, mut src: R, size: u64) -> Result<()> {
+    /// Say what the format said about this container, and everything it's in, which hasn't
+    /// been said yet; something from inside it is about to be emitted.
+    fn record(&self) -> Result<()> {
+        if let Some(parent) = self.parent {
+            parent.record()?;
+        }
+
+        let mut fields = self.notes.take();
+        if !self.recorded.replace(true) {
+            for (name, value) in &self.current.meta.format_fields {
+                fields.entry(name.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        if fields.is_empty() {
+            return Ok(());
+        }
+
+        let mut meta = self.current.meta.clone();
+        meta.container = ci_capnp::Container::Unpacked;
+        meta.format_fields = fields;
+        let record = crate::EntryBuilder {
+            path: self.current.path.clone(),
+            failure: None,
+            depth: self.current.depth,
+            meta,
+        };
+        self.write(&record, io::empty(), 0, &[])
+    }
+
+    /// Note something the format says about this whole container, e.g. a tar global header.
+    fn note(&self, fields: HashMap<String, Vec<u8>>) {
+        self.notes.borrow_mut().extend(fields);
+    }
+
+    fn write<R: io::Read>(
+        &self,
+        entry: &crate::EntryBuilder,
+        mut src: R,
+        size: u64,
+        digests: &[(String, Vec<u8>)],
+    ) -> Result<()> {
+        self.options.limits.check_entries(self.usage)?;
+
         let stdout = io::stdout();
         let mut stdout = stdout.lock();
 
+        if let OutputFormat::Jsonl = self.options.format {
+            let content: Option<&mut dyn io::Read> = if self.options.content_output {
+                Some(&mut src)
+            } else {
+                None
+            };
+            output_json::write_json(&mut stdout, entry, size, digests, content)?;
+            self.usage.emitted.set(self.usage.emitted.get() + 1);
+            return Ok(());
+        }
+
         output_capnp::write_capnp(
             &mut stdout,
-            &self.current,
+            entry,
             self.options.content_output,
             size,
+            digests,
         )?;
+        self.usage.emitted.set(self.usage.emitted.get() + 1);
 
         if self.options.content_output {
             let written = io::copy(&mut src, &mut stdout)?;
@@ -77,21 +225,68 @@ impl<'a> Unpacker<'a> {
         Ok(())
     }
 
-    fn from_file<'b>(path: &str, meta: fs::Metadata, options: &'b Options) -> Result<Unpacker<'b>> {
+    /// `more` bytes of this entry, after `so_far`, are about to be buffered.
+    pub fn charge(&self, so_far: u64, more: u64) -> Result<()> {
+        self.options.limits.charge(self.usage, so_far, more)
+    }
+
+    /// Buffer `from`, then unpack it, unless that'd go over a limit.
+    fn unpack_from<R: io::Read>(&mut self, from: R) -> Result<()> {
+        match TempFileTee::if_necessary(from, self) {
+            Ok(fd) => self.unpack(fd),
+            Err(e) => self.complete_over_limit(e),
+        }
+    }
+
+    /// Buffer `from`, then emit it whole, unless that'd go over a limit.
+    fn complete_from<R: io::Read>(&mut self, from: R) -> Result<()> {
+        match TempFileTee::if_necessary(from, self) {
+            Ok(fd) => self.complete(fd),
+            Err(e) => self.complete_over_limit(e),
+        }
+    }
+
+    /// `--max-entries` stops the whole input, but it's not a failure.
+    fn stop_at_entry_limit(&self, res: Result<()>) -> Result<()> {
+        match res {
+            Err(e) if is_entry_limit_error(&e) => self.log(1, || {
+                format!("stopped unpacking '{}': {:#}", self.current.path, e)
+            }),
+            other => other,
+        }
+    }
+
+    /// Emit the entry without its content, which there's too much of, and say why.
+    fn complete_over_limit(&mut self, error: anyhow::Error) -> Result<()> {
+        if !is_limit_error(&error) {
+            return Err(error);
+        }
+
+        self.log(1, || {
+            format!("not unpacking '{}': {:#}", self.current.path, error)
+        })?;
+        let message = format!("{:#}", error);
+        self.current.failure = Some(match self.current.failure {
+            Some(ArchiveReadFailure::Read(_)) => ArchiveReadFailure::Read(message),
+            _ => ArchiveReadFailure::Open(message),
+        });
+        self.complete_details(io::Cursor::new(&[]), 0)
+    }
+
+    fn from_file<'b>(
+        path: &path::Path,
+        meta: fs::Metadata,
+        options: &'b Options,
+        usage: &'b Usage,
+    ) -> Result<Unpacker<'b>> {
         use crate::stat::Stat;
 
         let stat: Stat = Stat::from(&meta);
 
         let item_type = if meta.is_dir() {
-            unreachable!()
+            ItemType::Directory
         } else if meta.file_type().is_symlink() {
-            match fs::read_link(path)?.to_str() {
-                Some(dest) => ItemType::SymbolicLink(dest.to_string()),
-                None => bail!(ErrorKind::UnsupportedFeature(format!(
-                    "{} is a symlink to an invaild utf-8 sequence",
-                    path
-                ))),
-            }
+            ItemType::SymbolicLink(fs::read_link(path)?.to_string_lossy().to_string())
         } else if meta.is_file() {
             ItemType::RegularFile
         } else if 0 == stat.mode {
@@ -109,8 +304,16 @@ impl<'a> Unpacker<'a> {
                 0b0001 => ItemType::Fifo,   // S_IFIFO
                 0b1100 => ItemType::Socket, // S_IFSOCK
 
-                0b0010 => panic!("TODO: char device"), // S_IFCHR
-                0b0110 => panic!("TODO: block device"), // S_IFBLK
+                0b0010 => {
+                    // S_IFCHR
+                    let (major, minor) = device_numbers(stat.rdev);
+                    ItemType::CharacterDevice { major, minor }
+                }
+                0b0110 => {
+                    // S_IFBLK
+                    let (major, minor) = device_numbers(stat.rdev);
+                    ItemType::BlockDevice { major, minor }
+                }
 
                 _ => bail!(ErrorKind::UnsupportedFeature(format!(
                     "unrecognised unix mode type {:b}",
@@ -141,22 +344,64 @@ impl<'a> Unpacker<'a> {
             },
             container: ci_capnp::Container::Unrecognised,
             item_type,
-            // TODO: extract xattrs?
-            xattrs: HashMap::new(),
+            xattrs: local_xattrs(path, !meta.file_type().is_symlink())
+                .with_context(|| "reading xattrs")?,
+            format_fields: HashMap::new(),
         };
 
+        let path = SList::head(Name::from_os(path.as_os_str()));
         Ok(Unpacker {
             options,
+            usage,
+            included: options.filter.includes(&path),
             current: crate::EntryBuilder {
                 depth: 0,
-                path: SList::head(path.to_string()),
+                path,
                 meta,
                 failure: None,
             },
+            parent: None,
+            recorded: Cell::new(false),
+            notes: RefCell::default(),
         })
     }
 
+    /// Whatever's piped in has no metadata, but is probably a file.
+    fn from_stdin<'b>(options: &'b Options, usage: &'b Usage) -> Unpacker<'b> {
+        let meta = Meta {
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            btime: 0,
+            ownership: ci_capnp::Ownership::Unknown,
+            item_type: ItemType::RegularFile,
+            container: ci_capnp::Container::Unrecognised,
+            xattrs: HashMap::new(),
+            format_fields: HashMap::new(),
+        };
+
+        let path = SList::head(Name::new("-"));
+        Unpacker {
+            options,
+            usage,
+            included: options.filter.includes(&path),
+            current: crate::EntryBuilder {
+                depth: 0,
+                path,
+                meta,
+                failure: None,
+            },
+            parent: None,
+            recorded: Cell::new(false),
+            notes: RefCell::default(),
+        }
+    }
+
     fn with_path(&self, path: &str) -> Unpacker {
+        self.with_name(Name::new(path))
+    }
+
+    fn with_name(&self, name: Name) -> Unpacker {
         let meta = Meta {
             atime: 0,
             mtime: 0,
@@ -166,21 +411,28 @@ impl<'a> Unpacker<'a> {
             item_type: ItemType::Unknown,
             container: ci_capnp::Container::Unrecognised,
             xattrs: HashMap::new(),
+            format_fields: HashMap::new(),
         };
 
+        let path = self.current.path.plus(name);
         Unpacker {
             options: self.options,
+            usage: self.usage,
+            included: self.included || self.options.filter.includes(&path),
             current: crate::EntryBuilder {
-                path: self.current.path.plus(path.to_string()),
+                path,
                 depth: self.current.depth + 1,
                 meta,
                 failure: None,
             },
+            parent: Some(self),
+            recorded: Cell::new(false),
+            notes: RefCell::default(),
         }
     }
 
     fn strip_compression_suffix(&self, suffix: &str) -> &str {
-        let our_name = self.current.path.inner().as_str();
+        let our_name = self.current.path.inner().text.as_str();
         if our_name.ends_with(suffix) {
             &our_name[..our_name.len() - suffix.len()]
         } else {
@@ -194,26 +446,96 @@ impl<'a> Unpacker<'a> {
     {
         let mut zip = zip::ZipArchive::new(from).with_context(|| "opening zip")?;
 
-        for i in 0..zip.len() {
-            let unpacker = {
-                let entry: zip::read::ZipFile = zip
+        // the local headers can have more in their extra fields, e.g. atimes,
+        // but the zip crate doesn't show them to us; go and get them ourselves
+        let starts = (0..zip.len())
+            .map(|i| zip.by_index_raw(i).map(|entry| entry.header_start()))
+            .collect::<zip::result::ZipResult<Vec<u64>>>()
+            .with_context(|| "listing entries")?;
+        let mut from = zip.into_inner();
+        let local_extras = starts
+            .into_iter()
+            .map(|start| zip_extra::local_extra(&mut from, start))
+            .collect::<io::Result<Vec<_>>>()
+            .with_context(|| "reading local headers")?;
+        let mut zip = zip::ZipArchive::new(from).with_context(|| "reopening zip")?;
+
+        if !zip.comment().is_empty() {
+            let comment = ("zip.comment".to_string(), zip.comment().to_vec());
+            self.note(std::iter::once(comment).collect());
+        }
+
+        for (i, local_extra) in local_extras.into_iter().enumerate() {
+            let mut unpacker = {
+                let mut entry: zip::read::ZipFile = zip
                     .by_index(i)
                     .with_context(|| format!("opening entry {}", i))?;
-                let mut unpacker = self.with_path(entry.name());
+                let mut extra = zip_extra::Extra::default();
+                extra.read(&local_extra);
+                extra.read(entry.extra_data());
+
+                // the zip crate has already used the UTF-8 flag, or guessed CP437
+                let name = match extra.unicode_path(entry.name_raw()) {
+                    Some(name) => name.to_string(),
+                    None => entry.name().to_string(),
+                };
+                let mut unpacker = self.with_name(Name::decoded(name, entry.name_raw()));
 
-                unpacker.current.meta.mtime = simple_time_tm(entry.last_modified())?;
-                unpacker.current.meta.ownership = match entry.unix_mode() {
+                let current = &mut unpacker.current;
+                current.meta.mtime = match extra.mtime {
+                    Some(mtime) => mtime,
+                    None => simple_time_tm(entry.last_modified())?,
+                };
+                current.meta.atime = extra.atime.unwrap_or(0);
+                current.meta.btime = extra.btime.unwrap_or(0);
+
+                let entity = |id: Option<u32>| {
+                    id.map(|id| ci_capnp::PosixEntity {
+                        id: u64::from(id),
+                        name: String::new(),
+                    })
+                };
+                current.meta.ownership = match entry.unix_mode() {
                     Some(mode) => ci_capnp::Ownership::Posix {
-                        user: None,
-                        group: None,
+                        user: entity(extra.uid),
+                        group: entity(extra.gid),
                         mode,
                     },
                     None => ci_capnp::Ownership::Unknown,
                 };
 
+                if !entry.comment().is_empty() {
+                    current.meta.format_fields.insert(
+                        "zip.comment".to_string(),
+                        entry.comment().as_bytes().to_vec(),
+                    );
+                }
+
+                // symlinks are stored as files containing their target
+                current.meta.item_type = match entry.unix_mode() {
+                    Some(mode) => mode_item_type(mode, (0, 0), || {
+                        let mut dest = String::new();
+                        entry
+                            .read_to_string(&mut dest)
+                            .with_context(|| "reading symlink target")?;
+                        Ok(dest)
+                    })?,
+                    None if entry.is_dir() => ItemType::Directory,
+                    None => ItemType::RegularFile,
+                };
+
                 unpacker
             };
 
+            match unpacker.current.meta.item_type {
+                ItemType::RegularFile | ItemType::Unknown => {}
+                _ => {
+                    unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+                    continue;
+                }
+            }
+
+            let before = self.usage.emitted.get();
             let res = {
                 let entry = zip.by_index(i)?;
                 let mut failing: Box<dyn Tee> = Box::new(FailingTee::new(entry));
@@ -221,10 +543,11 @@ impl<'a> Unpacker<'a> {
             };
 
             if self.is_format_error_result(&res)? {
+                unpacker.record_failure(&res, before);
+                // buffered, and so charged, like anything else which turns out not to be a container
                 let new_entry = zip.by_index(i)?;
-                let size = new_entry.size();
                 unpacker
-                    .complete_details(new_entry, size)
+                    .complete_from(new_entry)
                     .with_context(|| "..after rollback")?;
                 continue;
             }
@@ -234,6 +557,114 @@ impl<'a> Unpacker<'a> {
         Ok(())
     }
 
+    fn process_sevenz<T>(&self, inner: T) -> Result<()>
+    where
+        T: io::Read + io::Seek,
+    {
+        let mut archive = sevenz::SevenZ::open(inner).with_context(|| "opening archive")?;
+        if let Some(method) = archive.methods().into_iter().find(|m| !m.is_supported()) {
+            bail!(ErrorKind::UnsupportedFeature(format!(
+                "7z method: {:?}",
+                method
+            )));
+        }
+
+        // files in a solid folder are stored back to back, so read each folder once,
+        // along with any empty files or directories listed in between
+        let entries = archive.entries();
+        let mut rest = &entries[..];
+        while !rest.is_empty() {
+            let index = rest.iter().find_map(|entry| entry.folder);
+            let len = rest
+                .iter()
+                .position(|entry| entry.folder.is_some() && entry.folder != index)
+                .unwrap_or(rest.len());
+            let (run, tail) = rest.split_at(len);
+            rest = tail;
+
+            let mut folder = match index {
+                Some(index) => Some(
+                    archive
+                        .open_folder(index)
+                        .with_context(|| format!("opening folder {}", index))?,
+                ),
+                None => None,
+            };
+
+            for entry in run {
+                self.process_sevenz_entry(entry, folder.as_mut())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_sevenz_entry(
+        &self,
+        entry: &sevenz::Entry,
+        folder: Option<&mut Box<dyn io::Read + '_>>,
+    ) -> Result<()> {
+        let mut unpacker = self.with_path(&entry.path);
+        {
+            let current = &mut unpacker.current;
+            let time = |val: Option<u64>| val.map_or(0, simple_time_filetime);
+            current.meta.atime = time(entry.atime);
+            current.meta.mtime = time(entry.mtime);
+            current.meta.btime = time(entry.ctime);
+
+            if let Some(attributes) = entry.attributes {
+                current.meta.format_fields.insert(
+                    "7z.attributes".to_string(),
+                    format!("{:#x}", attributes).into_bytes(),
+                );
+            }
+            if let Some(mode) = entry.mode() {
+                current.meta.ownership = ci_capnp::Ownership::Posix {
+                    user: None,
+                    group: None,
+                    mode: mode & 0o7777,
+                };
+            }
+        }
+
+        let mut data = match (entry.folder, folder) {
+            (Some(_), Some(folder)) => folder.take(entry.size),
+            _ => {
+                unpacker.current.meta.item_type = if entry.is_dir {
+                    ItemType::Directory
+                } else {
+                    ItemType::RegularFile
+                };
+                return unpacker.complete_details(io::Cursor::new(&[]), 0);
+            }
+        };
+
+        // symlinks are stored as files containing their target
+        unpacker.current.meta.item_type = match entry.mode() {
+            Some(mode) => mode_item_type(mode, (0, 0), || {
+                let mut dest = String::new();
+                data.read_to_string(&mut dest)?;
+                Ok(dest)
+            })?,
+            None => ItemType::RegularFile,
+        };
+
+        match unpacker.current.meta.item_type {
+            ItemType::RegularFile | ItemType::Unknown => {
+                unpacker
+                    .unpack_from(&mut data)
+                    .with_context(|| format!("unpacking 7z entry: {}", entry.path))?;
+                // the next entry follows on in the folder, even if we stopped at a limit
+                io::copy(&mut data, &mut io::sink())?;
+                Ok(())
+            }
+            _ => {
+                io::copy(&mut data, &mut io::sink())?;
+                unpacker.complete_details(io::Cursor::new(&[]), 0)
+            }
+        }
+    }
+
     fn process_partition<T>(&self, inner: T) -> Result<()>
     where
         T: io::Read + io::Seek,
@@ -258,6 +689,173 @@ impl<'a> Unpacker<'a> {
         Ok(())
     }
 
+    fn process_iso<T>(&self, mut inner: T) -> Result<()>
+    where
+        T: io::Read + io::Seek,
+    {
+        if !iso9660::is_iso(&mut inner)? {
+            bail!(ErrorKind::Rewind);
+        }
+
+        let mut fs = iso9660::Iso::open(inner).with_context(|| "opening filesystem")?;
+
+        for entry in fs.entries().with_context(|| "listing directories")? {
+            let mut unpacker = self.with_path(&entry.path);
+            {
+                let current = &mut unpacker.current;
+                let time = |val: Option<time::OffsetDateTime>| val.map_or(0, simple_time_offset);
+                current.meta.atime = time(entry.atime);
+                current.meta.mtime = time(entry.mtime);
+                current.meta.ctime = time(entry.ctime);
+                current.meta.btime = time(entry.btime);
+
+                // plain ISO 9660 has no idea about permissions, or types beyond "directory"
+                current.meta.item_type = match entry.mode {
+                    Some(mode) => {
+                        current.meta.ownership = ci_capnp::Ownership::Posix {
+                            user: Some(ci_capnp::PosixEntity {
+                                id: u64::from(entry.uid),
+                                name: String::new(),
+                            }),
+                            group: Some(ci_capnp::PosixEntity {
+                                id: u64::from(entry.gid),
+                                name: String::new(),
+                            }),
+                            mode: mode & 0o7777,
+                        };
+
+                        mode_item_type(mode, entry.device, || {
+                            entry
+                                .link
+                                .clone()
+                                .ok_or_else(|| anyhow!("symlink without a target"))
+                        })?
+                    }
+                    None if entry.is_dir => ItemType::Directory,
+                    None => ItemType::RegularFile,
+                };
+            }
+
+            match unpacker.current.meta.item_type {
+                ItemType::RegularFile | ItemType::Unknown => {
+                    unpacker
+                        .unpack_from(fs.open_file(&entry))
+                        .with_context(|| format!("unpacking iso entry: {}", entry.path))?;
+                }
+                _ => unpacker.complete_details(io::Cursor::new(&[]), 0)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_squashfs<T>(&self, inner: T) -> Result<()>
+    where
+        T: io::Read + io::Seek,
+    {
+        let mut fs = squashfs::SquashFs::open(inner).with_context(|| "opening filesystem")?;
+        if !fs.compression().is_supported() {
+            bail!(ErrorKind::UnsupportedFeature(format!(
+                "squashfs compression: {:?}",
+                fs.compression()
+            )));
+        }
+
+        let mut links = HashMap::new();
+
+        for entry in fs.entries().with_context(|| "listing directories")? {
+            let mut unpacker = self.with_path(&entry.path);
+            {
+                let current = &mut unpacker.current;
+                current.meta.ownership = ci_capnp::Ownership::Posix {
+                    user: Some(ci_capnp::PosixEntity {
+                        id: u64::from(entry.uid),
+                        name: String::new(),
+                    }),
+                    group: Some(ci_capnp::PosixEntity {
+                        id: u64::from(entry.gid),
+                        name: String::new(),
+                    }),
+                    mode: entry.mode & 0o7777,
+                };
+
+                current.meta.mtime = simple_time_epoch_seconds(u64::from(entry.mtime));
+
+                current.meta.item_type = mode_item_type(entry.mode, entry.device, || {
+                    entry
+                        .link
+                        .clone()
+                        .ok_or_else(|| anyhow!("symlink without a target"))
+                })?;
+
+                current.meta.xattrs = entry.xattrs.clone();
+            }
+
+            match unpacker.current.meta.item_type {
+                ItemType::RegularFile if entry.nlink > 1 => {
+                    if let Some(dest) = links.get(&entry.inode_number) {
+                        unpacker.current.meta.item_type = ItemType::HardLink(String::clone(dest));
+                        unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+                        continue;
+                    }
+
+                    links.insert(entry.inode_number, entry.path.to_string());
+                    unpacker
+                        .unpack_from(fs.open_file(&entry))
+                        .with_context(|| format!("unpacking squashfs entry: {}", entry.path))?;
+                }
+                ItemType::RegularFile => {
+                    unpacker
+                        .unpack_from(fs.open_file(&entry))
+                        .with_context(|| format!("unpacking squashfs entry: {}", entry.path))?;
+                }
+                _ => unpacker.complete_details(io::Cursor::new(&[]), 0)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_fat<T>(&self, inner: T) -> Result<()>
+    where
+        T: io::Read + io::Seek,
+    {
+        let mut fs = fat::Fat::open(inner).with_context(|| "opening filesystem")?;
+        self.log(2, || {
+            format!(
+                "found {:?} filesystem in '{}'",
+                fs.variant(),
+                self.current.path.inner()
+            )
+        })?;
+
+        for entry in fs.entries().with_context(|| "listing directories")? {
+            let mut unpacker = self.with_path(&entry.path);
+            {
+                let current = &mut unpacker.current;
+                current.meta.atime = simple_time_fat(&entry.atime);
+                current.meta.mtime = simple_time_fat(&entry.mtime);
+                current.meta.btime = simple_time_fat(&entry.btime);
+                current.meta.item_type = if entry.is_dir {
+                    ItemType::Directory
+                } else {
+                    ItemType::RegularFile
+                };
+            }
+
+            if entry.is_dir {
+                unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+                continue;
+            }
+
+            unpacker
+                .unpack_from(fs.open_file(&entry)?)
+                .with_context(|| format!("unpacking fat entry: {}", entry.path))?;
+        }
+
+        Ok(())
+    }
+
     fn process_regular_inode<T>(
         &self,
         fs: &mut ext4::SuperBlock<T>,
@@ -314,13 +912,10 @@ impl<'a> Unpacker<'a> {
         match unpacker.current.meta.item_type {
             ItemType::RegularFile => {
                 // TODO: this should be a BufReaderTee, but BORROWS. HORRIBLE INEFFICIENCY
-                let tee = TempFileTee::if_necessary(
-                    fs.open(inode)
-                        .map_err(|e| anyhow!("todo: anyhow {:?}", e))?,
-                    &unpacker,
-                )
-                .context("tee")?;
-                unpacker.unpack(tee).context("unpacking")?;
+                let file = fs
+                    .open(inode)
+                    .map_err(|e| anyhow!("todo: anyhow {:?}", e))?;
+                unpacker.unpack_from(file).context("unpacking")?;
             }
             _ => {
                 unpacker.complete_details(io::Cursor::new(&[]), 0)?;
@@ -330,27 +925,88 @@ impl<'a> Unpacker<'a> {
         Ok(())
     }
 
+    /// Debs, static libraries, ipks..; the ar crate deals with the GNU and BSD long names.
+    fn process_ar<'c>(&self, fd: &mut Box<dyn Tee + 'c>) -> Result<()> {
+        let mut decoder = ar::Archive::new(fd);
+        while let Some(entry) = decoder.next_entry() {
+            let entry = entry?;
+            let mut unpacker = {
+                let header = entry.header();
+                let mut unpacker = self.with_name(Name::from_bytes(header.identifier()));
+
+                let current = &mut unpacker.current;
+                current.meta.mtime = simple_time_epoch_seconds(header.mtime());
+                current.meta.ownership = ci_capnp::Ownership::Posix {
+                    user: Some(ci_capnp::PosixEntity {
+                        id: u64::from(header.uid()),
+                        name: String::new(),
+                    }),
+                    group: Some(ci_capnp::PosixEntity {
+                        id: u64::from(header.gid()),
+                        name: String::new(),
+                    }),
+                    mode: header.mode() & 0o7777,
+                };
+                current.meta.item_type = ItemType::RegularFile;
+                unpacker
+            };
+
+            unpacker
+                .unpack_from(entry)
+                .with_context(|| format!("unpacking ar entry {}", unpacker.current.path))?;
+        }
+        Ok(())
+    }
+
     fn process_tar<'c>(&self, fd: &mut Box<dyn Tee + 'c>) -> Result<()> {
         let mut decoder = tar::Archive::new(fd);
-        for entry in decoder.entries()? {
-            let entry = entry.with_context(|| "parsing header")?;
 
-            let mut unpacker = {
-                let path = entry.path()?;
-                let path = path.to_str().ok_or_else(|| {
-                    ErrorKind::UnsupportedFeature(format!(
-                        "invalid path utf-8: {:?}",
-                        entry.path_bytes()
-                    ))
-                })?;
+        // pax records from global headers apply to every entry after them
+        let mut global = BTreeMap::new();
 
-                if "pax_global_header" == path {
-                    // TODO: maybe expose this data?
-                    // Just ignoring the entry is more consistent with "tar xf".
-                    continue;
+        for entry in decoder.entries()? {
+            let mut entry = entry.with_context(|| "parsing header")?;
+
+            if entry.header().entry_type().is_pax_global_extensions() {
+                // `tar xf` doesn't create a file for these, so neither do we
+                for extension in entry.pax_extensions()?.into_iter().flatten() {
+                    let extension = extension.with_context(|| "reading global pax header")?;
+                    global.insert(
+                        String::from_utf8_lossy(extension.key_bytes()).to_string(),
+                        extension.value_bytes().to_vec(),
+                    );
                 }
+                // so they're recorded, as `tar.global.*`, before the next thing emitted
+                self.note(
+                    global
+                        .iter()
+                        .map(|(key, value)| (format!("tar.global.{}", key), value.clone()))
+                        .collect(),
+                );
+                continue;
+            }
+
+            // in order, as some keys (GNU.sparse.offset) are repeated
+            let mut local = Vec::new();
+            for extension in entry.pax_extensions()?.into_iter().flatten() {
+                let extension = extension.with_context(|| "reading pax header")?;
+                local.push((
+                    String::from_utf8_lossy(extension.key_bytes()).to_string(),
+                    extension.value_bytes().to_vec(),
+                ));
+            }
+
+            let mut pax = global.clone();
+            pax.extend(local.iter().cloned());
 
-                self.with_path(path)
+            let sparse = sparse::Sparse::from_pax(&local).with_context(|| "reading sparse map")?;
+
+            let mut unpacker = {
+                // the header has a made-up name, like "GNUSparseFile.123/disk.img"
+                match pax.get("GNU.sparse.name") {
+                    Some(name) => self.with_name(Name::from_bytes(name)),
+                    None => self.with_name(Name::from_bytes(&entry.path_bytes())),
+                }
             };
 
             {
@@ -391,39 +1047,361 @@ impl<'a> Unpacker<'a> {
 
                 current.meta.mtime =
                     simple_time_epoch_seconds(header.mtime().with_context(|| "reading mtime")?);
+
+                // pax times are more precise, and the header doesn't have the others at all
+                for (key, value) in &pax {
+                    match key.as_str() {
+                        "mtime" => {
+                            if let Some(mtime) = pax::parse_time(value) {
+                                current.meta.mtime = mtime;
+                            }
+                        }
+                        "atime" => current.meta.atime = pax::parse_time(value).unwrap_or(0),
+                        "ctime" => current.meta.ctime = pax::parse_time(value).unwrap_or(0),
+                        _ => {
+                            if let Some((name, value)) = pax::xattr(key, value) {
+                                current.meta.xattrs.insert(name, value);
+                            }
+                        }
+                    }
+                }
+
+                current.meta.item_type = tar_item_type(&entry).with_context(|| "reading type")?;
             }
 
-            unpacker
-                .unpack(TempFileTee::if_necessary(entry, &unpacker)?)
-                .with_context(|| {
-                    format!("processing tar entry: {}", unpacker.current.path.inner())
+            match unpacker.current.meta.item_type {
+                ItemType::RegularFile | ItemType::Unknown if sparse.is_some() => {
+                    let sparse = sparse.unwrap();
+                    let map = match sparse.map {
+                        Some(map) => map,
+                        None => {
+                            sparse::read_map(&mut entry).with_context(|| "reading sparse map")?
+                        }
+                    };
+                    unpacker.current.meta.format_fields.insert(
+                        "tar.sparse.map".to_string(),
+                        sparse::Sparse::describe(&map).into_bytes(),
+                    );
+                    let reader = sparse::SparseReader::new(entry, map, sparse.size)?;
+                    unpacker.unpack_from(reader).with_context(|| {
+                        format!("processing tar entry: {}", unpacker.current.path.inner())
+                    })?;
+                }
+                ItemType::RegularFile | ItemType::Unknown => {
+                    unpacker.unpack_from(entry).with_context(|| {
+                        format!("processing tar entry: {}", unpacker.current.path.inner())
+                    })?;
+                }
+                _ => {
+                    unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn process_cpio(
+        &self,
+        from: &mut dyn io::BufRead,
+        decorate: &dyn Fn(&str, &mut Meta),
+    ) -> Result<()> {
+        let mut state = CpioState {
+            seen: HashMap::new(),
+            pending: BTreeMap::new(),
+            decorate,
+        };
+
+        if !self.process_cpio_stream(from, &mut state)? {
+            bail!(ErrorKind::UnsupportedFeature(
+                "unrecognised cpio compression".to_string()
+            ));
+        }
+
+        self.finish_cpio_segment(&mut state)
+    }
+
+    /// Inode numbers only mean anything within one archive, so forget them all; the
+    /// hardlinks which never got any content were all empty.
+    fn finish_cpio_segment(&self, state: &mut CpioState) -> Result<()> {
+        state.seen.clear();
+        for (_, pending) in std::mem::take(&mut state.pending) {
+            let mut target: Option<String> = None;
+            for (name, meta) in pending {
+                let path = name.text.clone();
+                let mut unpacker = self.with_name(name);
+                unpacker.current.meta = meta;
+                match target {
+                    Some(ref dest) => {
+                        unpacker.current.meta.item_type = ItemType::HardLink(dest.to_string())
+                    }
+                    None => target = Some(path),
+                }
+                unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_cpio_segment(
+        &self,
+        from: &mut dyn io::BufRead,
+        state: &mut CpioState,
+    ) -> Result<()> {
+        while let Some(header) = cpio::read_header(from).with_context(|| "parsing header")? {
+            if header.is_trailer() {
+                self.finish_cpio_segment(state)?;
+
+                // The kernel accepts multiple archives, optionally compressed, one after another,
+                // and treats them as one. Early microcode updates are delivered like this.
+                cpio::skip_zeros(from)?;
+                if !self.process_cpio_stream(from, state)? {
+                    self.log(1, || {
+                        format!(
+                            "ignoring trailing data in cpio archive '{}'",
+                            self.current.path
+                        )
+                    })?;
+                }
+                return Ok(());
+            }
+
+            self.process_cpio_entry(from, header, state)?;
+        }
+
+        Ok(())
+    }
+
+    /// A cpio archive, possibly compressed, or nothing at all. `false` if it's something else.
+    fn process_cpio_stream(
+        &self,
+        from: &mut dyn io::BufRead,
+        state: &mut CpioState,
+    ) -> Result<bool> {
+        let mut peek = [0u8; 64];
+        let found = read_all(&mut *from, &mut peek)?;
+        if 0 == found {
+            return Ok(true);
+        }
+
+        let peek = &peek[..found];
+        let mut rest = io::Cursor::new(peek).chain(from);
+
+        let identity = FileType::identify(peek);
+        self.log(2, || {
+            format!(
+                "found {} cpio stream in '{}'",
+                identity,
+                self.current.path.inner()
+            )
+        })?;
+
+        match identity {
+            FileType::Cpio => self.process_cpio_segment(&mut rest, state)?,
+            FileType::GZip => self
+                .process_cpio_segment(&mut io::BufReader::new(gzip::Decoder::new(rest)?), state)?,
+            FileType::Xz => self.process_cpio_segment(
+                &mut io::BufReader::new(xz2::bufread::XzDecoder::new(rest)),
+                state,
+            )?,
+            FileType::BZip2 => self.process_cpio_segment(
+                &mut io::BufReader::new(bzip2::read::BzDecoder::new(rest)),
+                state,
+            )?,
+            FileType::Zstd => self.process_cpio_segment(
+                &mut io::BufReader::new(zstd::stream::read::Decoder::with_buffer(rest)?),
+                state,
+            )?,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn process_cpio_entry(
+        &self,
+        from: &mut dyn io::BufRead,
+        header: cpio::Header,
+        state: &mut CpioState,
+    ) -> Result<()> {
+        let name = Name::from_bytes(&header.name);
+        let path = name.text.clone();
+
+        let mut unpacker = self.with_name(name.clone());
+        let mut data = (&mut *from).take(header.file_size);
+
+        {
+            let current = &mut unpacker.current;
+            current.meta.ownership = ci_capnp::Ownership::Posix {
+                user: Some(ci_capnp::PosixEntity {
+                    id: u64::from(header.uid),
+                    name: String::new(),
+                }),
+                group: Some(ci_capnp::PosixEntity {
+                    id: u64::from(header.gid),
+                    name: String::new(),
+                }),
+                mode: header.mode & 0o7777,
+            };
+
+            current.meta.mtime = simple_time_epoch_seconds(header.mtime);
+
+            current.meta.item_type =
+                mode_item_type(header.mode, (header.rdev_major, header.rdev_minor), || {
+                    let mut dest = Vec::new();
+                    data.read_to_end(&mut dest)?;
+                    Ok(String::from_utf8_lossy(&dest).to_string())
                 })?;
+
+            (state.decorate)(&path, &mut current.meta);
         }
+
+        match unpacker.current.meta.item_type {
+            ItemType::RegularFile if header.nlink > 1 => {
+                let key = (
+                    u64::from(header.dev_major) << 32 | u64::from(header.dev_minor),
+                    header.ino,
+                );
+
+                if let Some(dest) = state.seen.get(&key) {
+                    unpacker.current.meta.item_type = ItemType::HardLink(dest.to_string());
+                    unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+                } else if 0 == header.file_size {
+                    let meta = unpacker.current.meta.clone();
+                    state.pending.entry(key).or_default().push((name, meta));
+                } else {
+                    unpacker
+                        .unpack_from(&mut data)
+                        .with_context(|| format!("processing cpio entry: {}", path))?;
+
+                    for (link, meta) in state.pending.remove(&key).unwrap_or_default() {
+                        let mut link_unpacker = self.with_name(link);
+                        link_unpacker.current.meta = meta;
+                        link_unpacker.current.meta.item_type = ItemType::HardLink(path.to_string());
+                        link_unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+                    }
+
+                    state.seen.insert(key, path);
+                }
+            }
+            ItemType::RegularFile | ItemType::Unknown => {
+                unpacker
+                    .unpack_from(&mut data)
+                    .with_context(|| format!("processing cpio entry: {}", path))?;
+            }
+            _ => {
+                unpacker.complete_details(io::Cursor::new(&[]), 0)?;
+            }
+        }
+
+        // anything left is a repeat of a hardlink's content
+        let remaining = data.limit();
+        skip(&mut data, remaining)?;
+        skip(from, header.data_padding())?;
+
         Ok(())
     }
 
+    fn process_rpm(&self, from: &mut dyn io::BufRead) -> Result<()> {
+        rpm::read_lead(&mut *from).with_context(|| "reading lead")?;
+        rpm::Header::read(&mut *from, true).with_context(|| "reading signature")?;
+        let header = rpm::Header::read(&mut *from, false).with_context(|| "reading header")?;
+
+        let mut package = HashMap::new();
+        for &(tag, name) in &[
+            (rpm::NAME, "rpm.name"),
+            (rpm::VERSION, "rpm.version"),
+            (rpm::RELEASE, "rpm.release"),
+            (rpm::ARCH, "rpm.arch"),
+            (rpm::SOURCERPM, "rpm.sourcerpm"),
+        ] {
+            if let Some(value) = header.string(tag) {
+                package.insert(name.to_string(), value.into_bytes());
+            }
+        }
+
+        if let Some(epoch) = header.int32s(rpm::EPOCH).and_then(|v| v.first().cloned()) {
+            package.insert("rpm.epoch".to_string(), epoch.to_string().into_bytes());
+        }
+        self.note(package);
+
+        // everything else about the files is per-file, indexed by position in the header
+        let names = header.file_names();
+        let digests = header.strings(rpm::FILEDIGESTS).unwrap_or_default();
+        let users = header.strings(rpm::FILEUSERNAME).unwrap_or_default();
+        let groups = header.strings(rpm::FILEGROUPNAME).unwrap_or_default();
+        let algorithm = header.digest_algorithm();
+
+        let files: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+
+        let decorate = |path: &str, meta: &mut Meta| {
+            // the payload has paths like "./usr/bin/foo"; the header has "/usr/bin/foo"
+            let i = match files.get(path.strip_prefix('.').unwrap_or(path)) {
+                Some(&i) => i,
+                None => return,
+            };
+
+            if let Some(digest) = digests.get(i).filter(|d| !d.is_empty()) {
+                meta.format_fields.insert(
+                    "rpm.digest".to_string(),
+                    format!("{}:{}", algorithm, digest).into_bytes(),
+                );
+            }
+
+            if let ci_capnp::Ownership::Posix {
+                ref mut user,
+                ref mut group,
+                ..
+            } = meta.ownership
+            {
+                if let (Some(user), Some(name)) = (user.as_mut(), users.get(i)) {
+                    user.name = name.to_string();
+                }
+                if let (Some(group), Some(name)) = (group.as_mut(), groups.get(i)) {
+                    group.name = name.to_string();
+                }
+            }
+        };
+
+        self.process_cpio(from, &decorate)
+            .with_context(|| "reading payload")
+    }
+
     fn with_gzip(&self, header: &gzip::Header) -> Result<Unpacker> {
         let mtime = simple_time_epoch_seconds(header.modification_time() as u64);
         let name = match header.filename() {
-            Some(c_str) => c_str.to_str().map_err(|not_utf8| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!(
-                        "gzip member's name must be valid utf-8: {} {:?}",
-                        not_utf8,
-                        c_str.as_bytes()
-                    ),
-                )
-            })?,
-            None => self.strip_compression_suffix(".gz"),
+            Some(c_str) => Name::from_bytes(c_str.as_bytes()),
+            None => Name::new(self.strip_compression_suffix(".gz")),
         };
 
-        let mut unpacker = self.with_path(name);
+        let mut unpacker = self.with_name(name);
         unpacker.current.meta.mtime = mtime;
+
+        // if what's inside is a container, these come out on its record
+        let fields = &mut unpacker.current.meta.format_fields;
+        if let Some(comment) = header.comment() {
+            fields.insert("gzip.comment".to_string(), comment.as_bytes().to_vec());
+        }
+        for subfield in header.extra_field().iter().flat_map(|e| &e.subfields) {
+            fields.insert(
+                format!("gzip.extra.{}", gzip::subfield_name(subfield.id)),
+                subfield.data.clone(),
+            );
+        }
+
         Ok(unpacker)
     }
 
     fn unpack_or_die<'b>(&self, fd: &mut Box<dyn Tee + 'b>) -> Result<()> {
+        // don't even look inside; this is also how compressed streams get here
+        if self.options.filter.excludes(&self.current.path) {
+            return Ok(());
+        }
+
         if self.current.depth >= self.options.max_depth {
             bail!(ErrorKind::Rewind);
         }
@@ -434,9 +1412,10 @@ impl<'a> Unpacker<'a> {
         })?;
         match identity {
             FileType::GZip => {
-                let (attempt, unpacker) = {
+                let before = self.usage.emitted.get();
+                let (attempt, mut unpacker) = {
                     let br = BoxReader { inner: fd };
-                    let dec = gzip::Decoder::new(br)?;
+                    let dec = gzip::MultiDecoder::new(br)?;
 
                     let unpacker = self.with_gzip(dec.header())?;
 
@@ -450,11 +1429,9 @@ impl<'a> Unpacker<'a> {
                 };
 
                 if self.is_format_error_result(&attempt)? {
+                    unpacker.record_failure(&attempt, before);
                     fd.reset()?;
-                    unpacker.complete(TempFileTee::if_necessary(
-                        gzip::Decoder::new(fd)?,
-                        &unpacker,
-                    )?)?;
+                    unpacker.complete_from(gzip::MultiDecoder::new(fd)?)?;
                     Ok(())
                 } else {
                     attempt
@@ -464,46 +1441,55 @@ impl<'a> Unpacker<'a> {
             // xz and bzip2 have *nothing* in their header; no mtime, no name, no source OS, no nothing.
             FileType::Xz => self
                 .with_path(self.strip_compression_suffix(".xz"))
-                .unpack_stream_xz(fd)
+                .unpack_stream(fd, |from| Ok(Box::new(xz2::bufread::XzDecoder::new(from))))
                 .with_context(|| "unpacking xz"),
             FileType::BZip2 => self
                 .with_path(self.strip_compression_suffix(".bz2"))
-                .unpack_stream_bz2(fd)
+                .unpack_stream(fd, |from| Ok(Box::new(bzip2::read::BzDecoder::new(from))))
                 .with_context(|| "unpacking bz2"),
-
-            FileType::Deb => {
-                let mut decoder = ar::Archive::new(fd);
-                while let Some(entry) = decoder.next_entry() {
-                    let entry = entry?;
-                    let unpacker =
-                        self.with_path(&String::from_utf8(entry.header().identifier().to_vec())?);
-                    unpacker
-                        .unpack(TempFileTee::if_necessary(entry, &unpacker)?)
-                        .with_context(|| {
-                            format!("unpacking deb entry {}", unpacker.current.path)
-                        })?;
-                }
-                Ok(())
-            }
+            FileType::Zstd => self
+                .with_path(self.strip_compression_suffix(".zst"))
+                .unpack_stream(fd, |from| {
+                    Ok(Box::new(zstd::stream::read::Decoder::with_buffer(from)?))
+                })
+                .with_context(|| "unpacking zstd"),
+
+            FileType::Deb => self.process_ar(fd).with_context(|| "unpacking deb"),
+            FileType::Ar => self.process_ar(fd).with_context(|| "unpacking ar"),
             FileType::Tar => self.process_tar(fd).with_context(|| "unpacking tar"),
+            FileType::Rpm => self.process_rpm(fd).with_context(|| "unpacking rpm"),
+            FileType::Cpio => self
+                .process_cpio(fd, &|_, _| {})
+                .with_context(|| "unpacking cpio"),
             FileType::Zip => self
-                .process_zip(fd.as_seekable()?)
+                .process_zip(fd.as_seekable(self)?)
                 .with_context(|| "reading zip file"),
+            FileType::SevenZip => self
+                .process_sevenz(fd.as_seekable(self)?)
+                .with_context(|| "unpacking 7z"),
             FileType::Other => Err(ErrorKind::Rewind.into()),
             FileType::DiskImage => {
-                let mut fd = fd.as_seekable()?;
+                let mut fd = fd.as_seekable(self)?;
+
+                // "hybrid" isos have a partition table, so they can also be written to a usb stick
+                if iso9660::is_iso(&mut fd)? {
+                    return self.process_iso(fd).with_context(|| "unpacking hybrid iso");
+                }
+
                 for partition in
                     bootsector::list_partitions(&mut fd, &bootsector::Options::default())?
                 {
-                    let unpacker = self.with_path(format!("p{}", partition.id).as_str());
+                    let mut unpacker = self.with_path(format!("p{}", partition.id).as_str());
                     let mut part_reader = bootsector::open_partition(&mut fd, &partition)?;
 
+                    let before = self.usage.emitted.get();
                     let attempt = {
                         let mut failing: Box<dyn Tee> = Box::new(FailingTee::new(&mut part_reader));
                         unpacker.unpack_or_die(&mut failing)
                     };
 
                     if self.is_format_error_result(&attempt)? {
+                        unpacker.record_failure(&attempt, before);
                         part_reader.seek(io::SeekFrom::Start(0))?;
                         unpacker.complete_details(part_reader, partition.len)?;
                     } else {
@@ -512,46 +1498,50 @@ impl<'a> Unpacker<'a> {
                 }
                 Ok(())
             }
-            FileType::Ext4 => self.process_partition(fd.as_seekable()?),
-        }
-    }
-
-    // TODO: Work out how to generic these copy-pastes
-    fn unpack_stream_xz<'c>(&self, fd: &mut Box<dyn Tee + 'c>) -> Result<()> {
-        let attempt = {
-            let br = BoxReader { inner: fd };
-            let mut failing: Box<dyn Tee> =
-                Box::new(FailingTee::new(xz2::bufread::XzDecoder::new(br)));
-            self.unpack_or_die(&mut failing)
-        };
-
-        if self.is_format_error_result(&attempt)? {
-            fd.reset()?;
-            self.complete(TempFileTee::if_necessary(
-                xz2::bufread::XzDecoder::new(fd),
-                self,
-            )?)?;
-            Ok(())
-        } else {
-            attempt
+            FileType::Ext4 => self.process_partition(fd.as_seekable(self)?),
+            FileType::Fat => self
+                .process_fat(fd.as_seekable(self)?)
+                .with_context(|| "unpacking fat"),
+            FileType::SquashFs => self
+                .process_squashfs(fd.as_seekable(self)?)
+                .with_context(|| "unpacking squashfs"),
+            FileType::Iso9660 if fd.is_seekable() => self
+                .process_iso(fd.as_seekable(self)?)
+                .with_context(|| "unpacking iso"),
+            FileType::Iso9660 => {
+                // don't copy out a whole stream just to find it starts with zeros
+                let mut header = vec![0u8; iso9660::HEADER_LEN];
+                let found = read_all(&mut *fd, &mut header)?;
+                header.truncate(found);
+                if !iso9660::is_iso_header(&header) {
+                    bail!(ErrorKind::Rewind);
+                }
+                let mut rest: Box<dyn Tee> = Box::new(FailingTee::new(
+                    io::Cursor::new(header).chain(BoxReader { inner: fd }),
+                ));
+                self.process_iso(rest.as_seekable(self)?)
+                    .with_context(|| "unpacking iso")
+            }
         }
     }
 
-    // TODO: copy-paste of unpack_stream_xz
-    fn unpack_stream_bz2<'c>(&self, fd: &mut Box<dyn Tee + 'c>) -> Result<()> {
+    /// A compressed stream with nothing in its header; if what's inside isn't unpackable,
+    /// decompress it all over again, to emit it whole.
+    fn unpack_stream<'c, F>(&mut self, fd: &mut Box<dyn Tee + 'c>, decoder: F) -> Result<()>
+    where
+        F: for<'r> Fn(Box<dyn io::BufRead + 'r>) -> io::Result<Box<dyn io::Read + 'r>>,
+    {
+        let before = self.usage.emitted.get();
         let attempt = {
             let br = BoxReader { inner: fd };
-            let mut failing: Box<dyn Tee> =
-                Box::new(FailingTee::new(bzip2::read::BzDecoder::new(br)));
+            let mut failing: Box<dyn Tee> = Box::new(FailingTee::new(decoder(Box::new(br))?));
             self.unpack_or_die(&mut failing)
         };
 
         if self.is_format_error_result(&attempt)? {
+            self.record_failure(&attempt, before);
             fd.reset()?;
-            self.complete(TempFileTee::if_necessary(
-                bzip2::read::BzDecoder::new(fd),
-                self,
-            )?)?;
+            self.complete_from(decoder(Box::new(BoxReader { inner: fd }))?)?;
             Ok(())
         } else {
             attempt
@@ -581,12 +1571,35 @@ impl<'a> Unpacker<'a> {
         }
     }
 
-    fn unpack(&self, mut fd: Box<dyn Tee>) -> Result<()> {
+    /// Remember why this entry is being emitted whole, if it's because we failed to read it,
+    /// rather than because it just wasn't a container we understand.
+    ///
+    /// If nothing was emitted since `emitted_before`, we failed while opening it.
+    fn record_failure<T>(&mut self, res: &Result<T>, emitted_before: u64) {
+        if Some(FormatErrorType::Other) != classify_format_error_result(res) {
+            return;
+        }
+
+        let message = match res {
+            Ok(_) => return,
+            Err(e) => format!("{:#}", e),
+        };
+
+        self.current.failure = Some(if self.usage.emitted.get() == emitted_before {
+            ArchiveReadFailure::Open(message)
+        } else {
+            ArchiveReadFailure::Read(message)
+        });
+    }
+
+    fn unpack(&mut self, mut fd: Box<dyn Tee>) -> Result<()> {
+        let before = self.usage.emitted.get();
         let res = self
             .unpack_or_die(&mut fd)
             .with_context(|| "unpacking failed");
 
         if self.is_format_error_result(&res)? {
+            self.record_failure(&res, before);
             self.complete(fd)?;
             return Ok(());
         }
@@ -595,47 +1608,219 @@ impl<'a> Unpacker<'a> {
     }
 }
 
-pub fn process_real_path<P: AsRef<path::Path>>(path: P, options: &Options) -> Result<()> {
+/// Everything we can list, which includes capabilities (`security.capability`) and POSIX ACLs
+/// (`system.posix_acl_access`), but not `trusted.*` unless we're root.
+/// `follow`ing the symlink, if `path` is one, as for a command-line argument.
+fn local_xattrs(path: &path::Path, follow: bool) -> Result<HashMap<String, Vec<u8>>> {
+    let mut xattrs = HashMap::new();
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(xattrs);
+    }
+
+    let listed = if follow {
+        xattr::list_deref(path)
+    } else {
+        xattr::list(path)
+    };
+    let names = match listed {
+        Ok(names) => names,
+        // e.g. /proc, or FAT
+        Err(e) if io::ErrorKind::Unsupported == e.kind() => return Ok(xattrs),
+        Err(e) => return Err(e.into()),
+    };
+
+    for name in names {
+        // the attribute may have been removed since we listed it
+        let value = if follow {
+            xattr::get_deref(path, &name)?
+        } else {
+            xattr::get(path, &name)?
+        };
+        if let Some(value) = value {
+            xattrs.insert(name.to_string_lossy().to_string(), value);
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Split `st_rdev`, which every platform packs differently.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn device_numbers(rdev: u64) -> (u32, u32) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn device_numbers(rdev: u64) -> (u32, u32) {
+    (((rdev >> 24) & 0xff) as u32, (rdev & 0xff_ffff) as u32)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn device_numbers(rdev: u64) -> (u32, u32) {
+    (((rdev >> 8) & 0xff) as u32, (rdev & 0xffff_00ff) as u32)
+}
+
+/// The file type bits of a POSIX mode; symlinks find their target elsewhere.
+fn mode_item_type<F>(mode: u32, (major, minor): (u32, u32), link: F) -> Result<ItemType>
+where
+    F: FnOnce() -> Result<String>,
+{
+    Ok(match mode & 0o170000 {
+        0o100000 => ItemType::RegularFile,
+        0o040000 => ItemType::Directory,
+        0o010000 => ItemType::Fifo,
+        0o140000 => ItemType::Socket,
+        0o120000 => ItemType::SymbolicLink(link()?),
+        0o020000 => ItemType::CharacterDevice { major, minor },
+        0o060000 => ItemType::BlockDevice { major, minor },
+        _ => ItemType::Unknown,
+    })
+}
+
+fn tar_item_type<R: io::Read>(entry: &tar::Entry<R>) -> Result<ItemType> {
+    let header = entry.header();
+    Ok(match header.entry_type() {
+        // old (pre-POSIX) archives mark directories only with a trailing slash
+        tar::EntryType::Regular if entry.path_bytes().ends_with(b"/") => ItemType::Directory,
+        tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse => {
+            ItemType::RegularFile
+        }
+        tar::EntryType::Directory => ItemType::Directory,
+        tar::EntryType::Fifo => ItemType::Fifo,
+        tar::EntryType::Symlink => ItemType::SymbolicLink(tar_link_name(entry)?),
+        tar::EntryType::Link => ItemType::HardLink(tar_link_name(entry)?),
+        tar::EntryType::Char => ItemType::CharacterDevice {
+            major: header.device_major()?.unwrap_or(0),
+            minor: header.device_minor()?.unwrap_or(0),
+        },
+        tar::EntryType::Block => ItemType::BlockDevice {
+            major: header.device_major()?.unwrap_or(0),
+            minor: header.device_minor()?.unwrap_or(0),
+        },
+        // GNU incremental dumps' "dumpdir": a directory, with a listing as its content
+        other if b'D' == other.as_byte() => ItemType::Directory,
+        _ => ItemType::Unknown,
+    })
+}
+
+fn tar_link_name<R: io::Read>(entry: &tar::Entry<R>) -> Result<String> {
+    let dest = entry.link_name_bytes().unwrap_or_default();
+    Ok(String::from_utf8_lossy(&dest).to_string())
+}
+
+/// stdin can't seek, so it's read into memory, or a temporary file, first.
+pub fn process_stdin(options: &Options) -> Result<()> {
+    let usage = Usage::new(None);
+    let mut unpacker = Unpacker::from_stdin(options, &usage);
+    let stdin = io::stdin();
+    let mut fd = TempFileTee::if_necessary(stdin.lock(), &unpacker)?;
+    usage.set_input_len(fd.len_and_reset()?);
+    let res = unpacker.unpack(fd);
+    unpacker.stop_at_entry_limit(res)
+}
+
+/// Files we've seen with more than one link, by (device, inode), so we can emit later links
+/// as `HardLink`s to the first path, like `tar` does.
+#[derive(Default)]
+pub struct HardLinks {
+    seen: HashMap<(u64, u64), String>,
+}
+
+pub fn process_real_path<P: AsRef<path::Path>>(
+    path: P,
+    options: &Options,
+    links: &mut HardLinks,
+) -> Result<()> {
     let path = path.as_ref();
 
-    if !path.is_dir() {
-        let metadata = fs::symlink_metadata(path)?;
+    // follow what we were asked for, like `find -H`, unless it's dangling
+    let metadata = fs::metadata(path).or_else(|_| fs::symlink_metadata(path))?;
+    process_path(path, metadata, options, links)
+}
 
-        let unpacker = Unpacker::from_file(
-            path.to_str().ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("non-utf-8 filename found: {:?}", path),
-                )
-            })?,
-            metadata,
-            options,
-        )?;
+fn process_path(
+    path: &path::Path,
+    metadata: fs::Metadata,
+    options: &Options,
+    links: &mut HardLinks,
+) -> Result<()> {
+    use crate::stat::Stat;
 
-        return match unpacker.current.meta.item_type {
-            ItemType::Directory => unreachable!(),
+    let stat = Stat::from(&metadata);
+    let usage = Usage::new(Some(metadata.len()));
 
-            ItemType::SymbolicLink(_)
-            | ItemType::HardLink(_)
-            | ItemType::CharacterDevice { .. }
-            | ItemType::BlockDevice { .. }
-            | ItemType::Fifo
-            | ItemType::Socket => {
-                // can't actually read from these guys
-                unpacker.complete_details(io::Cursor::new(&[]), 0)
-            }
+    let mut unpacker = Unpacker::from_file(path, metadata, options, &usage)?;
+    if options.filter.excludes(&unpacker.current.path) {
+        return Ok(());
+    }
 
-            ItemType::Unknown | ItemType::RegularFile => {
-                let file = fs::File::open(path)?;
-                unpacker.unpack(Box::new(BufReaderTee::new(file)))
+    // directories can't be hardlinked; "nlink" is zero where we can't read it
+    if ItemType::Directory != unpacker.current.meta.item_type && stat.nlink > 1 {
+        use std::collections::hash_map::Entry;
+        match links.seen.entry((stat.dev, stat.ino)) {
+            Entry::Occupied(first) => {
+                unpacker.current.meta.item_type = ItemType::HardLink(first.get().to_string());
             }
-        };
+            Entry::Vacant(vacant) => {
+                vacant.insert(unpacker.current.path.inner().text.clone());
+            }
+        }
     }
 
+    match unpacker.current.meta.item_type {
+        ItemType::Directory => {
+            let res = unpacker.complete_details(io::Cursor::new(&[]), 0);
+            unpacker.stop_at_entry_limit(res)?;
+        }
+
+        ItemType::SymbolicLink(_)
+        | ItemType::HardLink(_)
+        | ItemType::CharacterDevice { .. }
+        | ItemType::BlockDevice { .. }
+        | ItemType::Fifo
+        | ItemType::Socket => {
+            // can't actually read from these guys
+            let res = unpacker.complete_details(io::Cursor::new(&[]), 0);
+            return unpacker.stop_at_entry_limit(res);
+        }
+
+        ItemType::Unknown | ItemType::RegularFile => {
+            let file = fs::File::open(path)?;
+            let res = unpacker.unpack(Box::new(BufReaderTee::new(file)));
+            return unpacker.stop_at_entry_limit(res);
+        }
+    }
+
+    // but not anything inside, so we can't loop
     for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
-        process_real_path(path, options)?;
+        let path = entry?.path();
+        process_path(&path, fs::symlink_metadata(&path)?, options, links)?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn device_numbers_split() {
+        // makedev(8, 1), for /dev/sda1
+        assert_eq!((8, 1), device_numbers(0x801));
+        // both numbers are split over the high and low halves
+        assert_eq!((0x1234, 0x56789), device_numbers(0x1000_5672_3489));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    fn device_numbers_split() {
+        assert_eq!((1, 3), device_numbers(0x0100_0003));
+    }
+}
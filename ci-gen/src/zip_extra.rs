@@ -0,0 +1,263 @@
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use crate::simple_time::simple_time_epoch_seconds;
+use crate::simple_time::simple_time_filetime;
+
+const LOCAL_HEADER_MAGIC: &[u8] = b"PK\x03\x04";
+const LOCAL_HEADER_LEN: usize = 30;
+
+const NTFS: u16 = 0x000a;
+const EXTENDED_TIMESTAMP: u16 = 0x5455;
+const INFO_ZIP_UNIX: u16 = 0x7875;
+const UNICODE_PATH: u16 = 0x7075;
+
+/// What the extra fields know beyond the DOS time and the mode. Times are in nanoseconds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Extra {
+    pub atime: Option<u64>,
+    pub mtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// The UTF-8 name, and the CRC-32 of the header's name it was made from.
+    unicode_path: Option<(u32, String)>,
+    /// NTFS times are more precise than extended timestamps, so win regardless of order.
+    ntfs: bool,
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+/// Unix seconds, which are signed here, though we can't represent anything before 1970.
+fn unix_time(bytes: &[u8]) -> Option<u64> {
+    u64::try_from(le32(bytes) as i32)
+        .ok()
+        .map(simple_time_epoch_seconds)
+}
+
+/// A variable-length little-endian id, as Info-ZIP stores them.
+fn unix_id(bytes: &[u8]) -> Option<u32> {
+    if bytes.len() > 4 && bytes[4..].iter().any(|&b| 0 != b) {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .take(4)
+            .rev()
+            .fold(0u32, |acc, &b| (acc << 8) | u32::from(b)),
+    )
+}
+
+impl Extra {
+    /// Apply all the fields we understand; both the local and central copies can be read in.
+    /// Broken fields are ignored, like everyone else does.
+    pub fn read(&mut self, mut fields: &[u8]) {
+        while fields.len() >= 4 {
+            let id = le16(fields);
+            let len = le16(&fields[2..]) as usize;
+            let data = match fields.get(4..4 + len) {
+                Some(data) => data,
+                None => return,
+            };
+            fields = &fields[4 + len..];
+
+            match id {
+                NTFS => self.read_ntfs(data),
+                EXTENDED_TIMESTAMP => self.read_extended_timestamp(data),
+                INFO_ZIP_UNIX => self.read_unix(data),
+                UNICODE_PATH => self.read_unicode_path(data),
+                _ => {}
+            }
+        }
+    }
+
+    /// Four reserved bytes, then (tag, size, data) attributes; tag 1 holds the three times.
+    fn read_ntfs(&mut self, data: &[u8]) {
+        let mut attributes = data.get(4..).unwrap_or_default();
+        while attributes.len() >= 4 {
+            let tag = le16(attributes);
+            let len = le16(&attributes[2..]) as usize;
+            let value = match attributes.get(4..4 + len) {
+                Some(value) => value,
+                None => return,
+            };
+            attributes = &attributes[4 + len..];
+
+            if 1 != tag || value.len() < 24 {
+                continue;
+            }
+
+            let time = |bytes: &[u8]| Some(simple_time_filetime(le64(bytes))).filter(|&t| 0 != t);
+            self.mtime = time(&value[0..]);
+            self.atime = time(&value[8..]);
+            self.btime = time(&value[16..]);
+            self.ntfs = true;
+        }
+    }
+
+    /// Flags say which of mtime, atime and creation time follow, but the central copy only
+    /// ever has the mtime, even if the flags say otherwise.
+    fn read_extended_timestamp(&mut self, data: &[u8]) {
+        let flags = match data.first() {
+            Some(&flags) => flags,
+            None => return,
+        };
+
+        let ntfs = self.ntfs;
+        let mut times = data[1..].chunks_exact(4).map(unix_time);
+        for (bit, field) in [
+            (1u8, &mut self.mtime),
+            (2, &mut self.atime),
+            (4, &mut self.btime),
+        ] {
+            if 0 == flags & bit {
+                continue;
+            }
+            let time = match times.next() {
+                Some(time) => time,
+                None => return,
+            };
+            if !ntfs && field.is_none() {
+                *field = time;
+            }
+        }
+    }
+
+    /// version (1), the CRC-32 of the header's name, then the name in UTF-8.
+    fn read_unicode_path(&mut self, data: &[u8]) {
+        if data.len() < 5 || 1 != data[0] {
+            return;
+        }
+
+        if let Ok(name) = std::str::from_utf8(&data[5..]) {
+            self.unicode_path = Some((le32(&data[1..]), name.to_string()));
+        }
+    }
+
+    /// The name from the Info-ZIP Unicode Path field, unless the name in the header
+    /// has been changed (by a tool which didn't know about the field) since it was written.
+    pub fn unicode_path(&self, raw_name: &[u8]) -> Option<&str> {
+        match self.unicode_path {
+            Some((crc, ref name)) if crc::crc32::checksum_ieee(raw_name) == crc => Some(name),
+            _ => None,
+        }
+    }
+
+    /// version (1), then a size-prefixed uid and gid.
+    fn read_unix(&mut self, data: &[u8]) {
+        if Some(&1) != data.first() {
+            return;
+        }
+
+        let uid_len = match data.get(1) {
+            Some(&len) => len as usize,
+            None => return,
+        };
+        let uid = match data.get(2..2 + uid_len) {
+            Some(uid) => uid,
+            None => return,
+        };
+        let gid_len = match data.get(2 + uid_len) {
+            Some(&len) => len as usize,
+            None => return,
+        };
+        let gid = match data.get(3 + uid_len..3 + uid_len + gid_len) {
+            Some(gid) => gid,
+            None => return,
+        };
+
+        self.uid = unix_id(uid);
+        self.gid = unix_id(gid);
+    }
+}
+
+/// The extra field from the local header, which has more in it than the central directory's.
+pub fn local_extra<R: Read + Seek>(mut from: R, header_start: u64) -> io::Result<Vec<u8>> {
+    from.seek(SeekFrom::Start(header_start))?;
+    let mut header = [0u8; LOCAL_HEADER_LEN];
+    from.read_exact(&mut header)?;
+    if LOCAL_HEADER_MAGIC != &header[..4] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid zip local header magic",
+        ));
+    }
+
+    let name_len = le16(&header[26..]) as i64;
+    let extra_len = le16(&header[28..]) as usize;
+    from.seek(SeekFrom::Current(name_len))?;
+    let mut extra = vec![0u8; extra_len];
+    from.read_exact(&mut extra)?;
+    Ok(extra)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_and_ids() {
+        let mut fields = vec![0x55, 0x54, 13, 0, 7];
+        for seconds in &[1_500_000_000u32, 1_500_000_001, 1_500_000_002] {
+            fields.extend_from_slice(&seconds.to_le_bytes());
+        }
+        fields.extend_from_slice(&[0x75, 0x78, 11, 0, 1, 4, 0xe8, 3, 0, 0, 4, 0xe9, 3, 0, 0]);
+
+        let mut extra = Extra::default();
+        extra.read(&fields);
+        assert_eq!(Some(1_500_000_000_000_000_000), extra.mtime);
+        assert_eq!(Some(1_500_000_001_000_000_000), extra.atime);
+        assert_eq!(Some(1_500_000_002_000_000_000), extra.btime);
+        assert_eq!(Some(1000), extra.uid);
+        assert_eq!(Some(1001), extra.gid);
+    }
+
+    #[test]
+    fn unicode_path() {
+        let raw_name = b"caf\x82";
+        let mut fields = vec![0x75, 0x70, 10, 0, 1];
+        fields.extend_from_slice(&crc::crc32::checksum_ieee(raw_name).to_le_bytes());
+        fields.extend_from_slice("caf\u{e9}".as_bytes());
+
+        let mut extra = Extra::default();
+        extra.read(&fields);
+        assert_eq!(Some("caf\u{e9}"), extra.unicode_path(raw_name));
+        assert_eq!(None, extra.unicode_path(b"renamed"));
+    }
+
+    #[test]
+    fn ntfs_wins() {
+        let filetime: u64 = (1_500_000_000 + 11_644_473_600) * 10_000_000 + 1234;
+        let mut ntfs = vec![0x0a, 0x00, 32, 0, 0, 0, 0, 0, 1, 0, 24, 0];
+        for _ in 0..3 {
+            ntfs.extend_from_slice(&filetime.to_le_bytes());
+        }
+        let timestamp = [0x55, 0x54, 5, 0, 1, 0x2f, 0x68, 0x59, 0x59];
+
+        let mut extra = Extra::default();
+        extra.read(&timestamp);
+        extra.read(&ntfs);
+        extra.read(&timestamp);
+        assert_eq!(Some(1_500_000_000_000_123_400), extra.mtime);
+        assert_eq!(None, extra.uid);
+
+        // truncated fields are ignored
+        extra.read(&ntfs[..20]);
+        assert_eq!(Some(1_500_000_000_000_123_400), extra.mtime);
+    }
+}
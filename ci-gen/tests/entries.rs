@@ -2,6 +2,7 @@ use std::io;
 use std::process;
 
 use ci_capnp::FileEntry;
+use ci_capnp::ItemType;
 
 use std::io::Read;
 
@@ -14,9 +15,68 @@ pub struct TestEntry {
 }
 
 pub fn entries(name: &str) -> io::Result<Vec<TestEntry>> {
-    let mut prog = process::Command::new(PROG)
+    entries_with(name, &[])
+}
+
+/// Whatever's written, in any `--format`.
+#[allow(dead_code)]
+pub fn output_with(name: &str, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = process::Command::new(PROG)
+        .args(args)
+        .arg(name)
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::inherit())
+        .output()?;
+    assert!(output.status.success());
+    Ok(output.stdout)
+}
+
+/// What's logged on stderr, e.g. with `-v`.
+#[allow(dead_code)]
+pub fn log_with(name: &str, args: &[&str]) -> io::Result<String> {
+    let output = process::Command::new(PROG)
+        .args(args)
         .arg(name)
         .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    Ok(String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+// not every test binary passes options
+#[allow(dead_code)]
+pub fn entries_with(name: &str, args: &[&str]) -> io::Result<Vec<TestEntry>> {
+    entries_from(name, args, process::Stdio::null())
+}
+
+/// Each entry's innermost path, type, length and content CRC, in `entries` order.
+#[allow(dead_code)]
+pub fn listing(entries: &[TestEntry]) -> Vec<(&str, &ItemType, u64, u32)> {
+    entries
+        .iter()
+        .map(|e| {
+            (
+                e.entry.paths[0].as_str(),
+                &e.entry.meta.item_type,
+                e.entry.len,
+                e.crc,
+            )
+        })
+        .collect()
+}
+
+/// With `stdin` for `-`, or for `-T -`.
+#[allow(dead_code)]
+pub fn entries_from(
+    name: &str,
+    args: &[&str],
+    stdin: process::Stdio,
+) -> io::Result<Vec<TestEntry>> {
+    let mut prog = process::Command::new(PROG)
+        .args(args)
+        .arg(name)
+        .stdin(stdin)
         .stdout(process::Stdio::piped())
         .stderr(process::Stdio::inherit())
         .spawn()?;
@@ -23,3 +23,77 @@ fn special_files() {
     assert_eq!(0, entry.entry.len);
     assert_eq!(ItemType::Fifo, entry.entry.meta.item_type);
 }
+
+#[test]
+fn real_directory_links() {
+    let dir = tempdir::TempDir::new("ci-real-directory").unwrap();
+    let root = dir.path().join("root");
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("a"), b"123456789").unwrap();
+    std::fs::hard_link(root.join("a"), root.join("b")).unwrap();
+
+    let entries = entries(root.to_str().unwrap()).unwrap();
+    let types: Vec<&ItemType> = entries.iter().map(|e| &e.entry.meta.item_type).collect();
+    assert_eq!(3, types.len());
+    assert_eq!(&ItemType::Directory, types[0]);
+
+    // whichever read_dir found first is the file; the other links to it
+    let (file, link) = match types[1] {
+        ItemType::RegularFile => (&entries[1], &entries[2]),
+        _ => (&entries[2], &entries[1]),
+    };
+    assert_eq!(0xe3069283, file.crc);
+    assert_eq!(
+        ItemType::HardLink(file.entry.paths[0].clone()),
+        link.entry.meta.item_type
+    );
+}
+
+/// A symlink we're given is followed, like `find -H`; ones inside aren't.
+#[test]
+fn real_symlink_argument() {
+    let dir = tempdir::TempDir::new("ci-real-symlink").unwrap();
+    let root = dir.path().join("root");
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("a"), b"123456789").unwrap();
+    std::os::unix::fs::symlink("a", root.join("inner")).unwrap();
+    let link = dir.path().join("link");
+    std::os::unix::fs::symlink(&root, &link).unwrap();
+
+    let link = link.to_str().unwrap();
+    let entries = entries(link).unwrap();
+    let found: Vec<(&str, &ItemType)> = entries
+        .iter()
+        .map(|e| (e.entry.paths[0].as_str(), &e.entry.meta.item_type))
+        .collect();
+    let a = format!("{}/a", link);
+    let inner = format!("{}/inner", link);
+    assert_eq!(
+        vec![
+            (link, &ItemType::Directory),
+            (a.as_str(), &ItemType::RegularFile),
+            (inner.as_str(), &ItemType::SymbolicLink("a".to_string())),
+        ],
+        found
+    );
+}
+
+#[test]
+fn real_xattrs() {
+    let dir = tempdir::TempDir::new("ci-real-xattrs").unwrap();
+    let file = dir.path().join("file");
+    std::fs::write(&file, b"123456789").unwrap();
+
+    // e.g. tmpfs on older kernels, or not Linux at all
+    if let Err(e) = xattr::set(&file, "user.ci-gen", b"value") {
+        eprintln!("skipping: can't set user xattrs here: {}", e);
+        return;
+    }
+
+    let entries = entries(file.to_str().unwrap()).unwrap();
+    assert_eq!(1, entries.len());
+    assert_eq!(
+        Some(&b"value".to_vec()),
+        entries[0].entry.meta.xattrs.get("user.ci-gen")
+    );
+}
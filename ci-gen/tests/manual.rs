@@ -1,6 +1,7 @@
 mod entries;
 use crate::entries::*;
 
+use ci_capnp::Container;
 use ci_capnp::ItemType;
 
 struct SimpleTest {
@@ -94,10 +95,512 @@ fn simple_tar_xz() {
     )
 }
 #[test]
+fn simple_tar_zst() {
+    check_simple(
+        "tests/examples/simple.tar.zst",
+        Some("tests/examples/simple.tar"),
+    )
+}
+#[test]
 fn simple_zip() {
     check_simple("tests/examples/simple.zip", None)
 }
 
+#[test]
+fn zip_extras() {
+    let mut entries = entries("tests/examples/extras.zip").unwrap();
+
+    // the archive's comment is said once, before its members
+    let archive = entries.remove(0).entry;
+    match archive.meta.container {
+        Container::Unpacked => {}
+        ref other => panic!("{:?}", other),
+    }
+    assert_eq!(
+        Some(&b"the archive".to_vec()),
+        archive.meta.format_fields.get("zip.comment")
+    );
+
+    assert_eq!(
+        vec![
+            ("link", &ItemType::SymbolicLink("plain".to_string()), 0, 0),
+            ("ntfs", &ItemType::RegularFile, 9, 0xe3069283),
+            ("plain", &ItemType::RegularFile, 9, 0xe3069283),
+        ],
+        listing(&entries)
+    );
+
+    let ntfs = &entries[1].entry.meta;
+    assert_eq!(1_500_000_000_000_123_400, ntfs.mtime);
+    assert_eq!(1_500_000_000_000_123_400, ntfs.btime);
+
+    let plain = &entries[2].entry.meta;
+    assert_eq!(1_500_000_000_000_000_000, plain.mtime);
+    assert_eq!(1_500_000_001_000_000_000, plain.atime);
+    match plain.ownership {
+        ci_capnp::Ownership::Posix {
+            user: Some(ref user),
+            group: Some(ref group),
+            ..
+        } => assert_eq!((1000, 1001), (user.id, group.id)),
+        ref other => panic!("unexpected ownership: {:?}", other),
+    }
+    assert_eq!(
+        Some(&b"a file".to_vec()),
+        plain.format_fields.get("zip.comment")
+    );
+    assert!(plain.xattrs.is_empty());
+    assert_eq!(None, ntfs.format_fields.get("zip.comment"));
+}
+
+#[test]
+fn tar_types() {
+    let entries = entries("tests/examples/types.tar").unwrap();
+    assert_eq!(
+        vec![
+            ("dir/", &ItemType::Directory, 0, 0),
+            ("dir/empty", &ItemType::RegularFile, 0, 0),
+            ("dir/fifo", &ItemType::Fifo, 0, 0),
+            ("dir/file", &ItemType::RegularFile, 9, 0xe3069283),
+            (
+                "dir/hard",
+                &ItemType::HardLink("dir/file".to_string()),
+                0,
+                0
+            ),
+            (
+                "dir/loop6",
+                &ItemType::BlockDevice { major: 7, minor: 6 },
+                0,
+                0
+            ),
+            (
+                "dir/null",
+                &ItemType::CharacterDevice { major: 1, minor: 3 },
+                0,
+                0
+            ),
+            (
+                "dir/soft",
+                &ItemType::SymbolicLink("file".to_string()),
+                0,
+                0
+            ),
+        ],
+        listing(&entries)
+    );
+}
+
+#[test]
+fn tar_pax() {
+    let entries = entries("tests/examples/pax.tar").unwrap();
+    assert_eq!(3, entries.len());
+
+    // the global header is about the tar, not only the entry after it
+    let tar = &entries[0].entry;
+    assert_eq!(1, tar.paths.len());
+    assert_eq!(
+        Some(&b"global".to_vec()),
+        tar.meta.format_fields.get("tar.global.comment")
+    );
+
+    let meta = &entries[1].entry.meta;
+    assert_eq!(1_500_000_000_123_456_789, meta.mtime);
+    assert_eq!(1_500_000_001_500_000_000, meta.atime);
+    assert_eq!(Some(&b"one".to_vec()), meta.xattrs.get("user.schily"));
+    assert_eq!(Some(&b"two".to_vec()), meta.xattrs.get("user.lib archive"));
+    assert!(meta.format_fields.is_empty());
+
+    let later = &entries[2].entry.meta;
+    assert_eq!(1_500_000_002_000_000_000, later.mtime);
+}
+
+/// Each global header is recorded on the tar, even when what's after it is a container or
+/// is excluded.
+#[test]
+fn tar_globals() {
+    let globals = |args: &[&str]| -> (Vec<String>, Vec<String>) {
+        let entries = entries_with("tests/examples/globals.tar", args).unwrap();
+        let (records, members): (Vec<_>, Vec<_>) =
+            entries.iter().partition(|e| 1 == e.entry.paths.len());
+        let comments = records
+            .iter()
+            .map(|e| {
+                assert!(matches!(e.entry.meta.container, Container::Unpacked));
+                let comment = &e.entry.meta.format_fields["tar.global.comment"];
+                String::from_utf8(comment.clone()).unwrap()
+            })
+            .collect();
+        let names = members
+            .iter()
+            .map(|e| {
+                assert!(e.entry.meta.format_fields.is_empty());
+                e.entry.paths[0].clone()
+            })
+            .collect();
+        (comments, names)
+    };
+
+    let (comments, names) = globals(&[]);
+    assert_eq!(vec!["first", "second"], comments);
+    assert_eq!(vec!["kept", "skipped", "inside"], names);
+
+    let (comments, names) = globals(&["--exclude", "skipped"]);
+    assert_eq!(vec!["first", "second"], comments);
+    assert_eq!(vec!["kept", "inside"], names);
+
+    // nothing came out after the first, so it's only seen as part of the second
+    let (comments, names) = globals(&["--exclude", "inner.tar"]);
+    assert_eq!(vec!["second"], comments);
+    assert_eq!(vec!["kept", "skipped"], names);
+}
+
+#[test]
+fn tar_sparse() {
+    for version in &["gnu", "0.0", "0.1", "1.0"] {
+        let path = format!("tests/examples/sparse-{}.tar", version);
+        let entries = entries(&path).unwrap();
+        assert_eq!(
+            vec![("disk.img", &ItemType::RegularFile, 65536, 0x8bdfdf95)],
+            listing(&entries),
+            "{}",
+            path
+        );
+
+        // the tar crate reassembles old GNU ones itself, so we never see their map
+        let map = entries[0].entry.meta.format_fields.get("tar.sparse.map");
+        if "gnu" != *version {
+            assert_eq!(
+                Some(&b"8192,4096,40960,4096,65536,0".to_vec()),
+                map,
+                "{}",
+                path
+            );
+        }
+    }
+}
+
+#[test]
+fn raw_names() {
+    let found = |path| -> Vec<(String, Option<Vec<u8>>)> {
+        entries(path)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.entry.paths[0].clone(), e.entry.raw_paths[0].clone()))
+            .collect()
+    };
+
+    assert_eq!(
+        vec![("caf\u{fffd}".to_string(), Some(b"caf\xe9".to_vec()))],
+        found("tests/examples/latin1.tar")
+    );
+
+    assert_eq!(
+        vec![
+            ("caf\u{e9}".to_string(), Some(b"caf\x82".to_vec())),
+            ("new-\u{e9}".to_string(), Some(b"old\x82".to_vec())),
+        ],
+        found("tests/examples/cp437.zip")
+    );
+}
+
+#[test]
+fn ar_long_names() {
+    for path in &["tests/examples/gnu.a", "tests/examples/bsd.a"] {
+        let entries = entries(path).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    "a_rather_long_object_name.o",
+                    &ItemType::RegularFile,
+                    9,
+                    0xe3069283
+                ),
+                ("short.o", &ItemType::RegularFile, 6, 0x353dd8be),
+            ],
+            listing(&entries),
+            "{}",
+            path
+        );
+
+        let meta = &entries[0].entry.meta;
+        assert_eq!(1_500_000_000_000_000_000, meta.mtime);
+        match meta.ownership {
+            ci_capnp::Ownership::Posix {
+                user: Some(ref user),
+                group: Some(ref group),
+                mode,
+            } => assert_eq!((1000, 1001, 0o640), (user.id, group.id, mode)),
+            ref other => panic!("unexpected ownership: {:?}", other),
+        }
+    }
+}
+
+/// An uncompressed archive, followed by a compressed one, like an early-microcode initramfs.
+/// Both use the same inode numbers, for different files.
+#[test]
+fn initramfs_cpio() {
+    let entries = entries("tests/examples/initramfs.cpio").unwrap();
+    assert_eq!(
+        vec![
+            (".", &ItemType::Directory, 0, 0),
+            ("bin", &ItemType::Directory, 0, 0),
+            ("bin/ash", &ItemType::RegularFile, 4, 0x9a528744),
+            (
+                "bin/busybox",
+                &ItemType::HardLink("bin/sh".to_string()),
+                0,
+                0
+            ),
+            ("bin/sh", &ItemType::RegularFile, 8, 0x67164eec),
+            (
+                "console",
+                &ItemType::CharacterDevice { major: 5, minor: 1 },
+                0,
+                0
+            ),
+            ("init", &ItemType::SymbolicLink("bin/sh".to_string()), 0, 0),
+            ("kernel", &ItemType::Directory, 0, 0),
+            ("kernel/firmware", &ItemType::RegularFile, 8, 0x2b99218e),
+            (
+                "kernel/microcode.bin",
+                &ItemType::RegularFile,
+                9,
+                0xe3069283
+            ),
+            ("kernel/orphan", &ItemType::RegularFile, 0, 0),
+        ],
+        listing(&entries)
+    );
+}
+
+#[test]
+fn gzip_members() {
+    let entries = entries("tests/examples/members.txt.gz").unwrap();
+    assert_eq!(1, entries.len());
+    let entry = &entries[0].entry;
+    assert_eq!("members.txt", entry.paths[0]);
+    // both members: "first\nsecond\n"
+    assert_eq!(13, entry.len);
+    assert_eq!(0xddf4bd15, entries[0].crc);
+    let fields = &entry.meta.format_fields;
+    assert_eq!(Some(&b"rotated".to_vec()), fields.get("gzip.comment"));
+    assert_eq!(Some(&b"hi".to_vec()), fields.get("gzip.extra.AP"));
+    assert!(entry.meta.xattrs.is_empty());
+}
+
+/// The tar is unpacked, so never appears itself; its record has the gzip header's comment.
+#[test]
+fn commented_tar_gz() {
+    let entries = entries("tests/examples/commented.tar.gz").unwrap();
+    assert_eq!(2, entries.len());
+
+    let tar = &entries[0].entry;
+    assert_eq!(
+        vec!["commented.tar", "tests/examples/commented.tar.gz"],
+        tar.paths
+    );
+    match tar.meta.container {
+        Container::Unpacked => {}
+        ref other => panic!("{:?}", other),
+    }
+    assert_eq!(
+        Some(&b"nightly build".to_vec()),
+        tar.meta.format_fields.get("gzip.comment")
+    );
+
+    let file = &entries[1].entry;
+    assert_eq!("hello.txt", file.paths[0]);
+    assert!(file.meta.format_fields.is_empty());
+}
+
+#[test]
+fn simple_rpm() {
+    let entries = entries("tests/examples/simple.rpm").unwrap();
+    assert_eq!(3, entries.len());
+
+    // the package, said once, before anything in it
+    let package = &entries[0].entry;
+    assert_eq!(vec!["tests/examples/simple.rpm"], package.paths);
+    assert_eq!(0, package.len);
+    match package.meta.container {
+        Container::Unpacked => {}
+        ref other => panic!("{:?}", other),
+    }
+    let fields = &package.meta.format_fields;
+    assert_eq!(Some(&b"simple".to_vec()), fields.get("rpm.name"));
+    assert_eq!(Some(&b"1.0".to_vec()), fields.get("rpm.version"));
+    assert_eq!(Some(&b"noarch".to_vec()), fields.get("rpm.arch"));
+
+    let file = &entries[2];
+    assert_eq!("./etc/foo.conf", file.entry.paths[0]);
+    assert_eq!(0xe3069283, file.crc);
+    assert!(file.entry.meta.xattrs.is_empty());
+    assert_eq!(
+        Some(&b"sha256:15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225".to_vec()),
+        file.entry.meta.format_fields.get("rpm.digest")
+    );
+    assert_eq!(None, file.entry.meta.format_fields.get("rpm.name"));
+    assert_eq!(None, entries[1].entry.meta.format_fields.get("rpm.digest"));
+}
+
+#[test]
+fn rock_ridge_iso() {
+    let entries = entries("tests/examples/simple.iso").unwrap();
+    assert_eq!(
+        vec![
+            ("dir", &ItemType::Directory, 0, 0),
+            ("dir/bar", &ItemType::RegularFile, 9, 0xe3069283),
+            ("foo.txt", &ItemType::RegularFile, 9, 0xe3069283),
+            (
+                "link",
+                &ItemType::SymbolicLink("/etc/passwd".to_string()),
+                0,
+                0
+            ),
+            (
+                "null",
+                &ItemType::CharacterDevice { major: 1, minor: 3 },
+                0,
+                0
+            ),
+        ],
+        listing(&entries)
+    );
+    assert_eq!(1_500_000_000_000_000_000, entries[2].entry.meta.mtime);
+}
+
+#[test]
+fn joliet_iso() {
+    let entries = entries("tests/examples/joliet.iso").unwrap();
+    let paths: Vec<&str> = entries.iter().map(|e| e.entry.paths[0].as_str()).collect();
+    assert_eq!(vec!["Dir", "Dir/Bar", "Foo.txt"], paths);
+}
+
+#[test]
+fn simple_squashfs() {
+    let entries = entries("tests/examples/simple.squashfs").unwrap();
+    assert_eq!(
+        vec![
+            ("big", &ItemType::RegularFile, 8195, 0x51ec877e),
+            ("dir", &ItemType::Directory, 0, 0),
+            ("dir/bar", &ItemType::RegularFile, 9, 0xe3069283),
+            ("foo", &ItemType::RegularFile, 13, 0x77bb1986),
+            ("hard", &ItemType::HardLink("foo".to_string()), 0, 0),
+            ("link", &ItemType::SymbolicLink("foo".to_string()), 0, 0),
+            (
+                "null",
+                &ItemType::CharacterDevice { major: 1, minor: 3 },
+                0,
+                0
+            ),
+        ],
+        listing(&entries)
+    );
+
+    assert_eq!(
+        Some(&b"hi".to_vec()),
+        entries[3].entry.meta.xattrs.get("user.comment")
+    );
+}
+
+#[test]
+fn fat_partition() {
+    let entries = entries("tests/examples/fat.img").unwrap();
+    assert!(entries.iter().all(|e| "p0" == e.entry.paths[1]));
+    assert_eq!(
+        vec![
+            ("DIR", &ItemType::Directory, 0, 0),
+            ("DIR/bar", &ItemType::RegularFile, 600, 0xb2062ed4),
+            ("Long File Name.txt", &ItemType::RegularFile, 9, 0xe3069283),
+        ],
+        listing(&entries)
+    );
+    assert_eq!(1_500_000_000_000_000_000, entries[2].entry.meta.mtime);
+}
+
+#[test]
+fn exfat() {
+    let entries = entries("tests/examples/exfat.img").unwrap();
+    assert_eq!(
+        vec![
+            ("hello.txt", &ItemType::RegularFile, 9, 0xe3069283),
+            ("sub", &ItemType::Directory, 0, 0),
+            // only two bytes were ever written
+            ("sub/x", &ItemType::RegularFile, 5, 0x927eae8d),
+        ],
+        listing(&entries)
+    );
+    // with the time zone applied
+    assert_eq!(1_500_000_000_000_000_000, entries[0].entry.meta.mtime);
+}
+
+#[test]
+fn simple_7z() {
+    let entries = entries("tests/examples/simple.7z").unwrap();
+    assert_eq!(
+        vec![
+            ("a", &ItemType::Directory, 0, 0),
+            // the first three files share a solid LZMA2 block
+            ("a/bar", &ItemType::RegularFile, 9, 0xe3069283),
+            ("b.txt", &ItemType::RegularFile, 600, 0xfea21b21),
+            ("d.txt", &ItemType::RegularFile, 800, 0xfd442107),
+            ("empty", &ItemType::RegularFile, 0, 0),
+            ("foo", &ItemType::RegularFile, 27, 0xc5969859),
+            ("link", &ItemType::SymbolicLink("foo".to_string()), 0, 0),
+            // BCJ, then LZMA
+            ("prog", &ItemType::RegularFile, 480, 0x42686a4e),
+        ],
+        listing(&entries)
+    );
+    assert_eq!(1_500_000_000_000_000_000, entries[2].entry.meta.mtime);
+    assert_eq!(
+        Some(&b"0x21".to_vec()),
+        entries[2].entry.meta.format_fields.get("7z.attributes")
+    );
+}
+
+#[test]
+fn encrypted_7z() {
+    let entries = entries("tests/examples/encrypted.7z").unwrap();
+    assert_eq!(1, entries.len());
+    match entries[0].entry.meta.container {
+        Container::OpenError(ref msg) => assert!(msg.contains("Aes"), "{}", msg),
+        ref other => panic!("unexpected container status: {:?}", other),
+    }
+}
+
+/// Made by 7-Zip, not by make-examples.sh; see there for where they're from.
+#[test]
+fn real_7z() {
+    let summary = |path: &str| -> Vec<(String, u64, u32)> {
+        entries(path)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.entry.paths[0].clone(), e.entry.len, e.crc))
+            .collect()
+    };
+
+    // LZMA2, then the x86 BCJ filter
+    assert_eq!(
+        vec![("decompress.exe".to_string(), 367104, 0x31e1ba34)],
+        summary("tests/examples/7zip-bcj.7z")
+    );
+    assert_eq!(
+        vec![("delta.txt".to_string(), 12, 0x3e589390)],
+        summary("tests/examples/7zip-delta.7z")
+    );
+    // both files are in one solid LZMA2 folder
+    assert_eq!(
+        vec![
+            ("7zip-archive".to_string(), 0, 0),
+            ("7zip-archive/hello".to_string(), 6, 0x353dd8be),
+            ("7zip-archive/world".to_string(), 6, 0xd4ad7373),
+        ],
+        summary("tests/examples/7zip-solid.7z")
+    );
+}
+
 /// tar has no error checking, and file data gets corrupted, so we don't detect this.
 #[test]
 fn byte_flip_tar() {
@@ -124,6 +627,10 @@ fn byte_flip_tar_gz() {
     // sorting in test means the archive comes out first (the order is undefined anyway...)
     assert_eq!(1, entries[0].entry.paths.len());
     assert_eq!(test_path, entries[0].entry.paths[0]);
+    match entries[0].entry.meta.container {
+        Container::ReadError(ref msg) => assert!(msg.contains("CRC32 mismatched"), "{}", msg),
+        ref other => panic!("unexpected container status: {:?}", other),
+    }
 
     // this crc should be correct
     assert_eq!(2806881067, entries[1].crc);
@@ -142,6 +649,10 @@ fn byte_flip_zip() {
     // sorting in test means the archive comes out first (the order is undefined anyway...)
     assert_eq!(1, entries[0].entry.paths.len());
     assert_eq!(test_path, entries[0].entry.paths[0]);
+    assert!(matches!(
+        entries[0].entry.meta.container,
+        Container::ReadError(_)
+    ));
 
     // this crc should be correct
     assert_eq!(2806881067, entries[1].crc);
@@ -160,3 +671,177 @@ fn zip_cd_files() {
     round_trips("tests/real/broken_cd.zip");
     round_trips("tests/real/incons-cdoffset.zip");
 }
+
+/// Entries over a limit are emitted empty, and say why; the rest carry on.
+#[test]
+fn limits() {
+    let test_path = "tests/examples/simple.tar.gz";
+    let entries = entries_with(test_path, &["--max-size", "5"]).unwrap();
+    assert_eq!(5, entries.len());
+    for entry in entries
+        .iter()
+        .filter(|e| ItemType::RegularFile == e.entry.meta.item_type)
+    {
+        assert_eq!(0, entry.entry.len);
+        match entry.entry.meta.container {
+            Container::OpenError(ref msg) => assert!(msg.contains("limit exceeded"), "{}", msg),
+            ref other => panic!("unexpected container status: {:?}", other),
+        }
+    }
+
+    // zip entries are charged when they're buffered, like anything else
+    let entries = entries_with("tests/examples/simple.zip", &["--max-size", "5"]).unwrap();
+    assert_eq!(5, entries.len());
+    for entry in entries
+        .iter()
+        .filter(|e| ItemType::RegularFile == e.entry.meta.item_type)
+    {
+        assert_eq!(0, entry.entry.len);
+        match entry.entry.meta.container {
+            Container::OpenError(ref msg) => assert!(msg.contains("limit exceeded"), "{}", msg),
+            ref other => panic!("unexpected container status: {:?}", other),
+        }
+    }
+
+    // the inner zip is buffered to look inside, then its 1000-byte file; each only counts once
+    let test_path = "tests/examples/nested.zip";
+    let entries = entries_with(test_path, &["--max-total", "2106"]).unwrap();
+    assert_eq!(1000, entries[0].entry.len);
+    let entries = entries_with(test_path, &["--max-total", "2105"]).unwrap();
+    assert_eq!(0, entries[0].entry.len);
+
+    // emitting just stops; nothing is emitted again
+    let entries = entries_with("tests/examples/simple.tar.gz", &["--max-entries", "2"]).unwrap();
+    assert_eq!(2, entries.len());
+    assert!(entries.iter().all(|e| 3 == e.entry.paths.len()));
+
+    // excluded entries don't count
+    let args = ["--max-entries", "1", "--exclude", "**/a*"];
+    let entries = entries_with("tests/examples/simple.tar", &args).unwrap();
+    assert_eq!(1, entries.len());
+    assert_eq!("foo", entries[0].entry.paths[0]);
+}
+
+#[test]
+fn hashes() {
+    let entries = entries_with("tests/examples/simple.tar", &["--hash", "crc32c,sha256"]).unwrap();
+    assert_eq!(5, entries.len());
+    for entry in &entries {
+        if ItemType::RegularFile != entry.entry.meta.item_type {
+            assert!(entry.entry.digests.is_empty());
+            continue;
+        }
+        assert_eq!(2, entry.entry.digests.len());
+        assert_eq!("crc32c", entry.entry.digests[0].0);
+        assert_eq!(entry.crc.to_be_bytes().to_vec(), entry.entry.digests[0].1);
+        assert_eq!("sha256", entry.entry.digests[1].0);
+        assert_eq!(32, entry.entry.digests[1].1.len());
+    }
+}
+
+#[test]
+fn jsonl() {
+    let output = output_with("tests/examples/latin1.tar", &["--format", "jsonl"]).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(1, lines.len());
+    let line = lines[0];
+    let start = "{\"len\":9,\"paths\":[\"caf\u{fffd}\",\"tests/examples/latin1.tar\"],\"rawPaths\":[\"Y2Fm6Q==\",null],";
+    assert!(line.starts_with(start), "{}", line);
+    // nanoseconds are too big to be JSON numbers
+    assert!(
+        line.contains(r#","mtime":"1500000000000000000","#),
+        "{}",
+        line
+    );
+    assert!(
+        line.contains(r#","type":"normal","container":"unrecognised","#),
+        "{}",
+        line
+    );
+    assert!(line.ends_with(r#","content":"MTIzNDU2Nzg5"}"#), "{}", line);
+}
+
+/// `-T`: by NUL, if the first name ends with one, otherwise by line; after the command line.
+#[test]
+fn files_from() {
+    let dir = tempdir::TempDir::new("ci-files-from").unwrap();
+    for (name, list) in [
+        (
+            "lines",
+            &b"tests/examples/pax.tar\ntests/examples/latin1.tar\n"[..],
+        ),
+        (
+            "nuls",
+            &b"tests/examples/pax.tar\0tests/examples/latin1.tar"[..],
+        ),
+    ] {
+        let list_path = dir.path().join(name);
+        std::fs::write(&list_path, list).unwrap();
+        let args = ["-T", list_path.to_str().unwrap()];
+        let entries = entries_with("tests/examples/simple.tar", &args).unwrap();
+        let mut inputs: Vec<&str> = entries
+            .iter()
+            .map(|e| e.entry.paths.last().unwrap().as_str())
+            .collect();
+        inputs.sort();
+        inputs.dedup();
+        assert_eq!(
+            vec![
+                "tests/examples/latin1.tar",
+                "tests/examples/pax.tar",
+                "tests/examples/simple.tar"
+            ],
+            inputs
+        );
+    }
+}
+
+/// `-` is stdin, as an input, or as the list of inputs.
+#[test]
+fn stdin() {
+    let tar = std::fs::File::open("tests/examples/latin1.tar").unwrap();
+    let entries = entries_from("-", &[], tar.into()).unwrap();
+    assert_eq!(1, entries.len());
+    assert_eq!(vec!["caf\u{fffd}", "-"], entries[0].entry.paths);
+    assert_eq!(0xe3069283, entries[0].crc);
+
+    let dir = tempdir::TempDir::new("ci-stdin").unwrap();
+    let list_path = dir.path().join("list");
+    std::fs::write(&list_path, b"tests/examples/latin1.tar\n").unwrap();
+    let list = std::fs::File::open(&list_path).unwrap();
+    let entries = entries_from("tests/examples/pax.tar", &["-T", "-"], list.into()).unwrap();
+    let inputs: Vec<&str> = entries
+        .iter()
+        .map(|e| e.entry.paths.last().unwrap().as_str())
+        .collect();
+    assert!(
+        inputs.contains(&"tests/examples/latin1.tar"),
+        "{:?}",
+        inputs
+    );
+    assert!(inputs.contains(&"tests/examples/pax.tar"), "{:?}", inputs);
+}
+
+/// Excluded entries, and everything under or inside them, aren't emitted, or even read.
+#[test]
+fn exclude() {
+    let names = |args: &[&str]| -> Vec<String> {
+        entries_with("tests/examples/simple.tar", args)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.entry.paths[0].clone())
+            .collect()
+    };
+    assert_eq!(vec!["foo"], names(&["--exclude", "a"]));
+    // the chain can start from the input's file name
+    assert_eq!(
+        vec!["a/", "a/bar", "foo"],
+        names(&["--exclude", "simple.tar/a/b"])
+    );
+
+    let args = ["-v", "--exclude", "simple.tar"];
+    let log = log_with("tests/examples/simple.tar.gz", &args).unwrap();
+    assert!(log.contains("as GZip"), "{}", log);
+    assert!(!log.contains("as Tar"), "{}", log);
+}
@@ -0,0 +1,516 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use ci_capnp::{Container, FileEntry, ItemType, Meta, Ownership};
+
+const NANOS: u64 = 1_000_000_000;
+
+/// What to do when something is already where an entry wants to go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Collision {
+    Fail,
+    Skip,
+    /// Remove whatever's there, even a whole directory.
+    Overwrite,
+    /// Put the entry next to it, as `name.1`, `name.2`, ...
+    Rename,
+}
+
+/// `ci-pipe extract`: every entry goes under `root`, with the containers it came from as
+/// directories, e.g. `outer.tar.gz/inner.tar/file`.
+///
+/// Archives are untrusted: names with `..`, or which are absolute, are refused, and nothing is
+/// ever written through a symlink, so symlinks can be restored verbatim without escaping `root`.
+pub struct Extractor {
+    root: PathBuf,
+    collision: Collision,
+    /// setuid, setgid and sticky; off unless asked for, as the input is untrusted.
+    special_bits: bool,
+    /// Extracting things into a directory changes its mtime, and its mode could stop us,
+    /// so these are applied at the end, relative to `root`.
+    directories: Vec<(PathBuf, Meta)>,
+    /// Directories which are containers we've seen the contents of, relative to `root`.
+    containers: HashSet<PathBuf>,
+}
+
+impl Extractor {
+    pub fn new(root: &Path, collision: Collision, special_bits: bool) -> io::Result<Extractor> {
+        fs::create_dir_all(root)?;
+        Ok(Extractor {
+            root: root.to_path_buf(),
+            collision,
+            special_bits,
+            directories: Vec::new(),
+            containers: HashSet::new(),
+        })
+    }
+
+    /// `content` must be exactly the entry's content. Entries which can't or mustn't be
+    /// extracted are warned about and skipped; only failures of our own are errors.
+    pub fn append<R: io::Read>(&mut self, entry: &FileEntry, content: R) -> io::Result<()> {
+        if !entry.content_follows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't extract a contentless stream; run ci-gen without --list",
+            ));
+        }
+
+        // this only says what a container's format said about it; what's in it follows
+        if let Container::Unpacked = entry.meta.container {
+            return Ok(());
+        }
+
+        let mut relative = match sanitise(&entry.paths) {
+            Ok(relative) => relative,
+            Err(reason) => return skip(&entry.paths.join(" / "), &reason),
+        };
+
+        if entry.paths.len() > 1 {
+            if let Ok(container) = sanitise(&entry.paths[1..]) {
+                self.containers.insert(container);
+            }
+        }
+
+        // ci-gen emits a container whole, after some of its contents, when it fails part way
+        // through; it can't go where they are, so goes beside them
+        if ItemType::Directory != entry.meta.item_type
+            && self.containers.contains(&relative)
+            && self.is_directory(&relative)
+        {
+            let beside = free_name(&self.root.join(&relative));
+            let beside = beside.strip_prefix(&self.root).expect("under root");
+            writeln!(
+                io::stderr(),
+                "{}: already unpacked from, so writing it as {}",
+                relative.display(),
+                beside.display()
+            )?;
+            relative = beside.to_path_buf();
+        }
+
+        let shown = relative.display().to_string();
+
+        let target = match self.prepare(&relative, &entry.meta.item_type)? {
+            Ok(target) => target,
+            Err(reason) => return skip(&shown, &reason),
+        };
+
+        let meta = &entry.meta;
+        match meta.item_type {
+            ItemType::RegularFile | ItemType::Unknown => {
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&target)?;
+                io::copy(&mut io::Read::take(content, entry.len), &mut file)?;
+            }
+            ItemType::Directory => {
+                if !target.is_dir() {
+                    fs::create_dir(&target)?;
+                }
+                set_xattrs(&target, meta);
+                self.directories.push((relative, meta.clone()));
+                return Ok(());
+            }
+            ItemType::SymbolicLink(ref dest) => {
+                match symlink(dest, &target) {
+                    Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                        return skip(&shown, "can't create symlinks here")
+                    }
+                    other => other?,
+                }
+                set_xattrs(&target, meta);
+                if 0 != meta.mtime {
+                    let mtime = file_time(meta.mtime);
+                    let _ = filetime::set_symlink_file_times(&target, mtime, mtime);
+                }
+                return Ok(());
+            }
+            ItemType::HardLink(ref dest) => {
+                // the target is in the same container
+                let mut chain = entry.paths.clone();
+                chain[0] = dest.to_string();
+                let existing = match sanitise(&chain) {
+                    Ok(existing) => existing,
+                    Err(reason) => return skip(&shown, &format!("link target: {}", reason)),
+                };
+                if self.through_symlink(&existing) {
+                    return skip(&shown, "link target is through a symlink");
+                }
+                let existing = self.root.join(existing);
+                match fs::symlink_metadata(&existing) {
+                    Ok(found) if found.is_file() => fs::hard_link(&existing, &target)?,
+                    _ => return skip(&shown, "link target hasn't been extracted"),
+                }
+                return Ok(());
+            }
+            ItemType::Fifo
+            | ItemType::Socket
+            | ItemType::CharacterDevice { .. }
+            | ItemType::BlockDevice { .. } => {
+                return skip(&shown, "can't create special files");
+            }
+        }
+
+        set_xattrs(&target, meta);
+        self.set_mode_and_time(&target, meta)
+    }
+
+    /// Now nothing else is going to be put in them. A later entry could have swapped one
+    /// for a symlink, so they're checked again, and skipped if so.
+    pub fn finish(self) -> io::Result<()> {
+        for (relative, meta) in self.directories.iter().rev() {
+            if self.is_directory(relative) {
+                self.set_mode_and_time(&self.root.join(relative), meta)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the directories leading up to `relative`, and clear (or dodge) anything in its
+    /// way, returning where to put it. The inner `Err` is why we won't.
+    fn prepare(
+        &self,
+        relative: &Path,
+        item_type: &ItemType,
+    ) -> io::Result<Result<PathBuf, String>> {
+        let mut target = self.root.clone();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            target.push(component);
+            let last = components.peek().is_none();
+
+            let existing = match fs::symlink_metadata(&target) {
+                Ok(existing) => existing,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    if !last {
+                        fs::create_dir(&target)?;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if existing.is_dir() && (!last || ItemType::Directory == *item_type) {
+                continue;
+            }
+
+            if !last && existing.file_type().is_symlink() {
+                return Ok(Err(format!("{} is a symlink", target.display())));
+            }
+
+            match self.collision {
+                Collision::Fail => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", target.display()),
+                    ))
+                }
+                Collision::Skip => return Ok(Err(format!("{} already exists", target.display()))),
+                Collision::Rename if last => target = free_name(&target),
+                Collision::Rename => {
+                    return Ok(Err(format!("{} isn't a directory", target.display())))
+                }
+                Collision::Overwrite => {
+                    if existing.is_dir() {
+                        fs::remove_dir_all(&target)?;
+                    } else {
+                        fs::remove_file(&target)?;
+                    }
+                    if !last {
+                        fs::create_dir(&target)?;
+                    }
+                }
+            }
+        }
+        Ok(Ok(target))
+    }
+
+    fn through_symlink(&self, relative: &Path) -> bool {
+        let mut path = self.root.clone();
+        relative.components().any(|component| {
+            path.push(component);
+            fs::symlink_metadata(&path)
+                .map(|found| found.file_type().is_symlink())
+                .unwrap_or(false)
+        })
+    }
+
+    /// A real directory, reached without going through any symlinks.
+    fn is_directory(&self, relative: &Path) -> bool {
+        !self.through_symlink(relative)
+            && fs::symlink_metadata(self.root.join(relative))
+                .map(|found| found.is_dir())
+                .unwrap_or(false)
+    }
+
+    fn set_mode_and_time(&self, path: &Path, meta: &Meta) -> io::Result<()> {
+        if let Ownership::Posix { mode, .. } = meta.ownership {
+            let mask = if self.special_bits { 0o7777 } else { 0o777 };
+            set_mode(path, mode & mask)?;
+        }
+        if 0 != meta.mtime {
+            let atime = if 0 != meta.atime {
+                meta.atime
+            } else {
+                meta.mtime
+            };
+            filetime::set_file_times(path, file_time(atime), file_time(meta.mtime))?;
+        }
+        Ok(())
+    }
+}
+
+/// The entry's paths, outermost first, checked for anything which would escape.
+/// The outermost is the input's own name, which we were given, so may be absolute, or
+/// go up; it's put under the root as if it didn't.
+fn sanitise(paths: &[String]) -> Result<PathBuf, String> {
+    let mut relative = PathBuf::new();
+    for (i, path) in paths.iter().enumerate().rev() {
+        let input = i == paths.len() - 1;
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => relative.push(name),
+                Component::CurDir => {}
+                Component::RootDir | Component::Prefix(_) | Component::ParentDir if input => {}
+                Component::RootDir | Component::Prefix(_) => {
+                    return Err(format!("absolute path: {}", path))
+                }
+                Component::ParentDir => return Err(format!("path with '..': {}", path)),
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err("empty path".to_string());
+    }
+    Ok(relative)
+}
+
+fn free_name(taken: &Path) -> PathBuf {
+    (1..)
+        .map(|i| {
+            let mut name = OsString::from(taken.file_name().expect("normal components only"));
+            name.push(format!(".{}", i));
+            taken.with_file_name(name)
+        })
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .expect("infinite")
+}
+
+fn skip(path: &str, reason: &str) -> io::Result<()> {
+    writeln!(io::stderr(), "skipping {}: {}", path, reason)
+}
+
+fn file_time(nanos: u64) -> filetime::FileTime {
+    filetime::FileTime::from_unix_time((nanos / NANOS) as i64, (nanos % NANOS) as u32)
+}
+
+/// Only where permitted: most are privileged, and not every filesystem has them.
+fn set_xattrs(path: &Path, meta: &Meta) {
+    for (name, value) in &meta.xattrs {
+        let _ = xattr::set(path, name, value);
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// There's nothing to map a POSIX mode on to.
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(dest: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(dest, path)
+}
+
+/// Windows wants to know what they point at, and privileges to make them.
+#[cfg(not(unix))]
+fn symlink(_dest: &str, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no symlinks"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::collections::HashMap;
+
+    use ci_capnp::Container;
+
+    use super::*;
+
+    fn entry(paths: &[&str], item_type: ItemType, mode: u32) -> FileEntry {
+        FileEntry {
+            len: 0,
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+            raw_paths: Vec::new(),
+            digests: Vec::new(),
+            content_follows: true,
+            meta: Meta {
+                atime: 0,
+                mtime: 1_500_000_000 * NANOS,
+                ctime: 0,
+                btime: 0,
+                ownership: Ownership::Posix {
+                    user: None,
+                    group: None,
+                    mode,
+                },
+                item_type,
+                container: Container::Unrecognised,
+                xattrs: HashMap::new(),
+                format_fields: HashMap::new(),
+            },
+        }
+    }
+
+    fn file(paths: &[&str]) -> FileEntry {
+        entry(paths, ItemType::RegularFile, 0o644)
+    }
+
+    fn strings(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn sanitising() {
+        assert_eq!(
+            Ok(PathBuf::from("tmp/outer.tar/a/b")),
+            sanitise(&strings(&["a/./b", "/tmp/outer.tar"]))
+        );
+        assert_eq!(
+            Ok(PathBuf::from("x.tar/a")),
+            sanitise(&strings(&["a", "../x.tar"]))
+        );
+        assert!(sanitise(&strings(&["../evil", "outer.tar"])).is_err());
+        assert!(sanitise(&strings(&["a/../../evil", "outer.tar"])).is_err());
+        assert!(sanitise(&strings(&["/etc/passwd", "outer.tar"])).is_err());
+        assert!(sanitise(&strings(&["/"])).is_err());
+        assert!(sanitise(&strings(&["."])).is_err());
+    }
+
+    #[test]
+    fn symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let escape = outside.path().to_str().unwrap();
+
+        let mut extractor = Extractor::new(root.path(), Collision::Fail, false).unwrap();
+        let link = entry(&["esc", "t.tar"], ItemType::SymbolicLink(escape.into()), 0);
+        extractor.append(&link, io::empty()).unwrap();
+        extractor
+            .append(&file(&["esc/pwned", "t.tar"]), io::empty())
+            .unwrap();
+        let hard = entry(&["h", "t.tar"], ItemType::HardLink("esc/x".into()), 0);
+        fs::write(outside.path().join("x"), b"secret").unwrap();
+        extractor.append(&hard, io::empty()).unwrap();
+        extractor.finish().unwrap();
+
+        assert!(!outside.path().join("pwned").exists());
+        assert!(!root.path().join("t.tar/h").exists());
+        assert_eq!(
+            escape,
+            fs::read_link(root.path().join("t.tar/esc"))
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn directory_swapped_for_symlink() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let before = fs::metadata(outside.path()).unwrap().permissions();
+
+        let mut extractor = Extractor::new(root.path(), Collision::Overwrite, false).unwrap();
+        let dir = entry(&["d", "t.tar"], ItemType::Directory, 0o4777);
+        extractor.append(&dir, io::empty()).unwrap();
+        let link = outside.path().to_str().unwrap().into();
+        let link = entry(&["d", "t.tar"], ItemType::SymbolicLink(link), 0);
+        extractor.append(&link, io::empty()).unwrap();
+        extractor.finish().unwrap();
+
+        assert_eq!(before, fs::metadata(outside.path()).unwrap().permissions());
+    }
+
+    #[test]
+    fn special_bits() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = tempfile::tempdir().unwrap();
+        for special_bits in [false, true] {
+            let mut extractor =
+                Extractor::new(root.path(), Collision::Overwrite, special_bits).unwrap();
+            let suid = entry(&["suid", "t.tar"], ItemType::RegularFile, 0o4755);
+            extractor.append(&suid, io::empty()).unwrap();
+            extractor.finish().unwrap();
+            let mode = fs::metadata(root.path().join("t.tar/suid"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(if special_bits { 0o4755 } else { 0o755 }, mode & 0o7777);
+        }
+    }
+
+    #[test]
+    fn collisions() {
+        let root = tempfile::tempdir().unwrap();
+        let relative = Path::new("t.tar/a");
+        let a = root.path().join(relative);
+        fs::create_dir(root.path().join("t.tar")).unwrap();
+        fs::write(&a, b"first").unwrap();
+
+        let prepare = |collision| {
+            Extractor::new(root.path(), collision, false)
+                .unwrap()
+                .prepare(relative, &ItemType::RegularFile)
+        };
+
+        assert_eq!(
+            io::ErrorKind::AlreadyExists,
+            prepare(Collision::Fail).unwrap_err().kind()
+        );
+        assert!(prepare(Collision::Skip).unwrap().is_err());
+        assert_eq!(
+            root.path().join("t.tar/a.1"),
+            prepare(Collision::Rename).unwrap().unwrap()
+        );
+        assert!(a.exists());
+        assert_eq!(a, prepare(Collision::Overwrite).unwrap().unwrap());
+        assert!(!a.exists());
+
+        // a directory of the same name is only in the way of something that isn't one
+        fs::create_dir(&a).unwrap();
+        let dir = Extractor::new(root.path(), Collision::Fail, false)
+            .unwrap()
+            .prepare(relative, &ItemType::Directory);
+        assert_eq!(a, dir.unwrap().unwrap());
+    }
+
+    #[test]
+    fn rolled_back_container() {
+        let root = tempfile::tempdir().unwrap();
+        let mut extractor = Extractor::new(root.path(), Collision::Fail, false).unwrap();
+        extractor
+            .append(&file(&["a", "outer.tar"]), io::empty())
+            .unwrap();
+        let mut whole = file(&["outer.tar"]);
+        whole.len = 5;
+        extractor.append(&whole, &b"whole"[..]).unwrap();
+        extractor.finish().unwrap();
+
+        assert!(root.path().join("outer.tar/a").is_file());
+        assert_eq!(
+            b"whole",
+            &fs::read(root.path().join("outer.tar.1")).unwrap()[..]
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use std::io;
+use std::io::Write;
+
+use ci_capnp::{Container, FileEntry, ItemType, Ownership};
+
+const NANOS: u64 = 1_000_000_000;
+
+/// The biggest numbers which fit in ustar's octal fields; anything bigger goes in a pax record.
+const MAX_SIZE: u64 = 0o77777777777;
+const MAX_ID: u64 = 0o7777777;
+
+/// `ci-pipe tar`: every entry becomes a member of one pax archive, named by its `paths`,
+/// so the nesting is still visible, but nothing needs unpacking twice.
+pub struct Flattener<W: io::Write> {
+    builder: tar::Builder<W>,
+    separator: String,
+}
+
+impl<W: io::Write> Flattener<W> {
+    pub fn new(to: W, separator: &str) -> Flattener<W> {
+        Flattener {
+            builder: tar::Builder::new(to),
+            separator: separator.to_string(),
+        }
+    }
+
+    /// Like `join_backwards`, but of the original bytes, which may not have been UTF-8.
+    /// Archives are relative, and `paths` from a real filesystem may not be.
+    fn name(&self, paths: &[&[u8]]) -> Vec<u8> {
+        let mut name = Vec::new();
+        for path in (1..paths.len().saturating_sub(1)).rev() {
+            name.extend_from_slice(paths[path]);
+            name.extend_from_slice(self.separator.as_bytes());
+        }
+        name.extend_from_slice(paths[0]);
+
+        let mut name = &name[..];
+        loop {
+            if let Some(rest) = name.strip_prefix(b"/") {
+                name = rest;
+            } else if let Some(rest) = name.strip_prefix(b"./") {
+                name = rest;
+            } else {
+                return name.to_vec();
+            }
+        }
+    }
+
+    /// `content` must be exactly the entry's content; sockets can't be in a tar, so are skipped.
+    pub fn append<R: io::Read>(&mut self, entry: &FileEntry, content: R) -> io::Result<()> {
+        if !entry.content_follows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't write a tar from a contentless stream; run ci-gen without --list",
+            ));
+        }
+
+        // this only says what a container's format said about it; what's in it follows
+        if let Container::Unpacked = entry.meta.container {
+            return Ok(());
+        }
+
+        let mut paths = raw_paths(entry);
+        let mut name = self.name(&paths);
+        let (entry_type, link) = match entry.meta.item_type {
+            ItemType::RegularFile | ItemType::Unknown => (tar::EntryType::Regular, None),
+            ItemType::Directory => {
+                if !name.ends_with(b"/") {
+                    name.push(b'/');
+                }
+                (tar::EntryType::Directory, None)
+            }
+            ItemType::Fifo => (tar::EntryType::Fifo, None),
+            ItemType::Socket => {
+                writeln!(
+                    io::stderr(),
+                    "skipping socket: {}",
+                    String::from_utf8_lossy(&name)
+                )?;
+                return Ok(());
+            }
+            ItemType::SymbolicLink(ref dest) => {
+                (tar::EntryType::Symlink, Some(dest.as_bytes().to_vec()))
+            }
+            ItemType::HardLink(ref dest) => {
+                // the target is in the same container, so gets the same prefix
+                paths[0] = dest.as_bytes();
+                (tar::EntryType::Link, Some(self.name(&paths)))
+            }
+            ItemType::CharacterDevice { .. } => (tar::EntryType::Char, None),
+            ItemType::BlockDevice { .. } => (tar::EntryType::Block, None),
+        };
+
+        let mut records: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(entry_type);
+
+        if !set_field(&mut header.as_old_mut().name, &name) {
+            records.push(("path".to_string(), name.clone()));
+        }
+        if let Some(link) = link {
+            if !set_field(&mut header.as_old_mut().linkname, &link) {
+                records.push(("linkpath".to_string(), link));
+            }
+        }
+
+        let size = match entry_type {
+            tar::EntryType::Regular => entry.len,
+            _ => 0,
+        };
+        header.set_size(size);
+        if size > MAX_SIZE {
+            records.push(("size".to_string(), size.to_string().into_bytes()));
+        }
+
+        let meta = &entry.meta;
+        header.set_mtime(meta.mtime / NANOS);
+        if !meta.mtime.is_multiple_of(NANOS) {
+            records.push(("mtime".to_string(), pax_time(meta.mtime)));
+        }
+        if 0 != meta.atime {
+            records.push(("atime".to_string(), pax_time(meta.atime)));
+        }
+        if 0 != meta.ctime {
+            records.push(("ctime".to_string(), pax_time(meta.ctime)));
+        }
+
+        match meta.ownership {
+            Ownership::Posix {
+                ref user,
+                ref group,
+                mode,
+            } => {
+                header.set_mode(mode & 0o7777);
+                for (entity, id_key, name_key) in [(user, "uid", "uname"), (group, "gid", "gname")]
+                {
+                    let entity = match entity {
+                        Some(entity) => entity,
+                        None => continue,
+                    };
+                    if entity.id > MAX_ID {
+                        records.push((id_key.to_string(), entity.id.to_string().into_bytes()));
+                    }
+                    let ustar = header.as_ustar_mut().expect("ustar header");
+                    let (id, name) = match id_key {
+                        "uid" => (&mut ustar.uid, &mut ustar.uname),
+                        _ => (&mut ustar.gid, &mut ustar.gname),
+                    };
+                    set_octal(id, entity.id.min(MAX_ID));
+                    if !set_field(name, entity.name.as_bytes()) {
+                        records.push((name_key.to_string(), entity.name.clone().into_bytes()));
+                    }
+                }
+            }
+            Ownership::Unknown => header.set_mode(match entry_type {
+                tar::EntryType::Directory => 0o755,
+                _ => 0o644,
+            }),
+        }
+
+        if let ItemType::CharacterDevice { major, minor } | ItemType::BlockDevice { major, minor } =
+            meta.item_type
+        {
+            header.set_device_major(major)?;
+            header.set_device_minor(minor)?;
+        }
+
+        let mut names: Vec<&String> = meta.xattrs.keys().collect();
+        names.sort();
+        for name in names {
+            records.push((format!("SCHILY.xattr.{}", name), meta.xattrs[name].clone()));
+        }
+
+        if !records.is_empty() {
+            let data = pax_records(&records);
+            let mut pax = tar::Header::new_ustar();
+            pax.set_entry_type(tar::EntryType::XHeader);
+            set_field(
+                &mut pax.as_old_mut().name,
+                &[&b"PaxHeaders/"[..], &name].concat(),
+            );
+            pax.set_mode(0o644);
+            pax.set_size(data.len() as u64);
+            pax.set_cksum();
+            self.builder.append(&pax, &data[..])?;
+        }
+
+        header.set_cksum();
+        self.builder.append(&header, io::Read::take(content, size))
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.builder.into_inner()?.flush()
+    }
+}
+
+/// Each of `paths`, as it was before being decoded.
+fn raw_paths(entry: &FileEntry) -> Vec<&[u8]> {
+    entry
+        .paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| match entry.raw_paths.get(i) {
+            Some(Some(raw)) => &raw[..],
+            _ => path.as_bytes(),
+        })
+        .collect()
+}
+
+/// NUL-padded, or truncated if it doesn't fit, in which case `false`.
+fn set_field(field: &mut [u8], value: &[u8]) -> bool {
+    let len = value.len().min(field.len());
+    field.iter_mut().for_each(|b| *b = 0);
+    field[..len].copy_from_slice(&value[..len]);
+    len == value.len()
+}
+
+fn set_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+fn pax_time(nanos: u64) -> Vec<u8> {
+    format!("{}.{:09}", nanos / NANOS, nanos % NANOS).into_bytes()
+}
+
+/// "%d %s=%s\n", where the length includes the digits of the length itself.
+fn pax_records(records: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (key, value) in records {
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + 1;
+        while len != rest + len.to_string().len() {
+            len = rest + len.to_string().len();
+        }
+        data.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    use ci_capnp::{Meta, PosixEntity};
+
+    use super::*;
+
+    fn entry(paths: &[&str], item_type: ItemType, len: u64) -> FileEntry {
+        FileEntry {
+            len,
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+            raw_paths: Vec::new(),
+            digests: Vec::new(),
+            content_follows: true,
+            meta: Meta {
+                atime: 0,
+                mtime: 1_500_000_000 * NANOS,
+                ctime: 0,
+                btime: 0,
+                ownership: Ownership::Posix {
+                    user: Some(PosixEntity {
+                        id: 1000,
+                        name: "user".to_string(),
+                    }),
+                    group: None,
+                    mode: 0o100640,
+                },
+                item_type,
+                container: Container::Unrecognised,
+                xattrs: HashMap::new(),
+                format_fields: HashMap::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn records() {
+        assert_eq!(
+            b"11 key=val\n".to_vec(),
+            pax_records(&[("key".to_string(), b"val".to_vec())])
+        );
+
+        // 98 bytes without the length, which is then three digits, not two
+        let data = pax_records(&[("path".to_string(), vec![b'a'; 91])]);
+        assert_eq!(101, data.len());
+        assert!(data.starts_with(b"101 path=aaa"));
+    }
+
+    #[test]
+    fn fields() {
+        let mut field = [b'x'; 8];
+        set_octal(&mut field, 0o755);
+        assert_eq!(b"0000755\0", &field);
+
+        let mut field = [b'x'; 4];
+        assert!(set_field(&mut field, b"ab"));
+        assert_eq!(b"ab\0\0", &field);
+        assert!(!set_field(&mut field, b"abcdef"));
+        assert_eq!(b"abcd", &field);
+    }
+
+    #[test]
+    fn names() {
+        let flattener = Flattener::new(io::sink(), "/");
+        assert_eq!(b"abs/file".to_vec(), flattener.name(&[b"/abs/file"]));
+        assert_eq!(b"file".to_vec(), flattener.name(&[b"/./file"]));
+        assert_eq!(
+            b"dir/a.tar/inner".to_vec(),
+            flattener.name(&[b"inner", b"./dir/a.tar", b"input"])
+        );
+    }
+
+    #[test]
+    fn contentless() {
+        let mut flattener = Flattener::new(io::sink(), "/");
+        let mut entry = entry(&["file"], ItemType::RegularFile, 0);
+        entry.content_follows = false;
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            flattener.append(&entry, io::empty()).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let long = format!("{}/file", "d".repeat(120));
+        let mut odd = entry(&["caf\u{fffd}", "input"], ItemType::RegularFile, 3);
+        odd.raw_paths = vec![Some(b"caf\xe9".to_vec()), None];
+        let mut xattr = entry(&["xattr", "input"], ItemType::RegularFile, 0);
+        xattr
+            .meta
+            .xattrs
+            .insert("user.comment".to_string(), b"hi".to_vec());
+        // what the format said isn't filesystem metadata, so doesn't get written
+        xattr
+            .meta
+            .format_fields
+            .insert("zip.comment".to_string(), b"hello".to_vec());
+        let mut record = entry(&["input"], ItemType::RegularFile, 0);
+        record.meta.container = Container::Unpacked;
+
+        let mut data = Vec::new();
+        let mut flattener = Flattener::new(&mut data, "/");
+        flattener.append(&record, io::empty()).unwrap();
+        flattener
+            .append(
+                &entry(&[&long, "input"], ItemType::RegularFile, 5),
+                &b"hello"[..],
+            )
+            .unwrap();
+        flattener
+            .append(
+                &entry(&["link", "input"], ItemType::SymbolicLink(long.clone()), 0),
+                io::empty(),
+            )
+            .unwrap();
+        flattener
+            .append(
+                &entry(&["hard", "input"], ItemType::HardLink(long.clone()), 0),
+                io::empty(),
+            )
+            .unwrap();
+        flattener.append(&odd, &b"abc"[..]).unwrap();
+        flattener.append(&xattr, io::empty()).unwrap();
+        flattener.finish().unwrap();
+
+        let mut archive = tar::Archive::new(&data[..]);
+        let mut found = Vec::new();
+        for member in archive.entries().unwrap() {
+            let mut member = member.unwrap();
+            let header = member.header();
+            assert_eq!(0o640, header.mode().unwrap());
+            assert_eq!(1_500_000_000, header.mtime().unwrap());
+            assert_eq!(1000, header.uid().unwrap());
+            assert_eq!(Some("user"), header.username().unwrap());
+
+            let link = member.link_name_bytes().map(|link| link.to_vec());
+            let xattrs: Vec<String> = member
+                .pax_extensions()
+                .unwrap()
+                .into_iter()
+                .flatten()
+                .map(|record| record.unwrap().key().unwrap().to_string())
+                .filter(|key| key.starts_with("SCHILY.xattr."))
+                .collect();
+            let path = member.path_bytes().to_vec();
+            let mut content = Vec::new();
+            member.read_to_end(&mut content).unwrap();
+            found.push((path, link, content, xattrs));
+        }
+
+        let none: Vec<String> = Vec::new();
+        assert_eq!(
+            vec![
+                (
+                    long.clone().into_bytes(),
+                    None,
+                    b"hello".to_vec(),
+                    none.clone()
+                ),
+                (
+                    b"link".to_vec(),
+                    Some(long.clone().into_bytes()),
+                    Vec::new(),
+                    none.clone()
+                ),
+                (
+                    b"hard".to_vec(),
+                    Some(long.into_bytes()),
+                    Vec::new(),
+                    none.clone()
+                ),
+                (b"caf\xe9".to_vec(), None, b"abc".to_vec(), none),
+                (
+                    b"xattr".to_vec(),
+                    None,
+                    Vec::new(),
+                    vec!["SCHILY.xattr.user.comment".to_string()]
+                ),
+            ],
+            found
+        );
+    }
+}
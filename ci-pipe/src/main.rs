@@ -1,5 +1,9 @@
 use std::io;
-use std::process;
+use std::path::Path;
+
+mod extract;
+mod flatten;
+mod run;
 
 use clap::{App, Arg, SubCommand};
 
@@ -64,43 +68,116 @@ fn grep<R: io::Read>(mut from: &mut R, regex: &regex::Regex) -> bool {
     })
 }
 
-fn direct_run<R: io::Read>(mut from: &mut R, cmd: &[&str]) -> bool {
-    with_entries(&mut from, move |mut from, entry| {
-        // skip others; assuming they're empty
-        match entry.meta.item_type {
-            ci_capnp::ItemType::RegularFile => {}
-            _ => {
-                assert_eq!(0, entry.len);
+/// 0, 2 for a fatal error, or 1 if commands failed, and we were collecting those.
+fn direct_run<R: io::Read>(
+    mut from: &mut R,
+    cmd: &[&str],
+    policy: run::FailurePolicy,
+    jobs: usize,
+) -> u8 {
+    let mut runner = run::Runner::new(cmd, policy, jobs);
+    let ok = with_entries(&mut from, |from, entry| {
+        // records of what a container's format said about it aren't files
+        let file = match entry.meta.container {
+            ci_capnp::Container::Unpacked => false,
+            _ => matches!(entry.meta.item_type, ci_capnp::ItemType::RegularFile),
+        };
+
+        if !entry.content_follows {
+            if !file {
                 return Ok(());
             }
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Can't do anything for contentless streams",
+            ));
         }
 
-        if !entry.content_follows {
+        let mut content = from.take(entry.len);
+        // others are skipped, even if they have content, e.g. an unknown type
+        if file {
+            runner.run(entry, &mut content)?;
+        }
+        // whatever the command didn't read
+        io::copy(&mut content, &mut io::sink())?;
+        if 0 != content.limit() {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
-                "Can't do anything for contentless streams",
+                "stream ended inside an entry",
             ));
         }
+        Ok(())
+    });
 
-        let mut child = process::Command::new(cmd[0])
-            .args(&cmd[1..])
-            .env("TAR_REALNAME", join_backwards(&entry.paths, "/ /"))
-            .env("TAR_FILENAME", &entry.paths[0])
-            .env("TAR_SIZE", format!("{}", entry.len))
-            .stdin(process::Stdio::piped())
-            .stdout(process::Stdio::inherit())
-            .stderr(process::Stdio::inherit())
-            .spawn()?;
+    if !ok {
+        return 2;
+    }
 
-        assert_eq!(
-            entry.len,
-            copy_upto(&mut from, &mut child.stdin.as_mut().unwrap(), entry.len)?
-        );
+    match runner.finish() {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "fatal: waiting for commands: {}", e);
+            2
+        }
+    }
+}
 
-        assert!(child.wait()?.success());
+fn extract<R: io::Read>(
+    mut from: &mut R,
+    to: &Path,
+    collision: extract::Collision,
+    special_bits: bool,
+) -> bool {
+    let mut extractor = match extract::Extractor::new(to, collision, special_bits) {
+        Ok(extractor) => extractor,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "fatal: creating {}: {}", to.display(), e);
+            return false;
+        }
+    };
+    with_entries(&mut from, |from, entry| {
+        let mut content = from.take(entry.len);
+        extractor.append(entry, &mut content)?;
+        // skipped entries don't read their content
+        io::copy(&mut content, &mut io::sink())?;
+        if 0 != content.limit() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended inside an entry",
+            ));
+        }
+        Ok(())
+    }) && match extractor.finish() {
+        Ok(()) => true,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "fatal: finishing directories: {}", e);
+            false
+        }
+    }
+}
 
+fn flatten<R: io::Read, W: io::Write>(mut from: &mut R, to: W, separator: &str) -> bool {
+    let mut tar = flatten::Flattener::new(to, separator);
+    with_entries(&mut from, |from, entry| {
+        let mut content = from.take(entry.len);
+        tar.append(entry, &mut content)?;
+        // anything which isn't a regular file doesn't get its content written out
+        io::copy(&mut content, &mut io::sink())?;
+        if 0 != content.limit() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended inside an entry",
+            ));
+        }
         Ok(())
-    })
+    }) && match tar.finish() {
+        Ok(()) => true,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "fatal: finishing tar: {}", e);
+            false
+        }
+    }
 }
 
 fn copy_upto<R: ?Sized, W: ?Sized>(reader: &mut R, writer: &mut W, how_much: u64) -> io::Result<u64>
@@ -148,6 +225,39 @@ fn real_main() -> u8 {
                     .help("pattern to search for"),
             ),
         )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Write everything out under a directory, containers becoming directories")
+                .arg(
+                    Arg::with_name("on-collision")
+                        .long("on-collision")
+                        .takes_value(true)
+                        .possible_values(["fail", "skip", "overwrite", "rename"])
+                        .default_value("fail")
+                        .help("When something is already there: rename gives `name.1`, etc."),
+                )
+                .arg(
+                    Arg::with_name("keep-special-bits")
+                        .long("keep-special-bits")
+                        .help("Keep setuid, setgid and sticky bits, which the input could abuse"),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .required(true)
+                        .help("Directory to extract into, created if necessary"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tar")
+                .about("Re-pack everything as one flat pax tar, on stdout")
+                .arg(
+                    Arg::with_name("separator")
+                        .long("separator")
+                        .takes_value(true)
+                        .default_value("/")
+                        .help("Put between the names of containers and their members"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("run")
                 .setting(clap::AppSettings::TrailingVarArg)
@@ -157,15 +267,27 @@ fn real_main() -> u8 {
                         .long("sh")
                         .help("Run with `sh -c`, and ssh-like quoting behaviour"),
                 )
-                //                      .arg(Arg::with_name("command-failure")
-                //                          .long("command-failure")
-                //                          .takes_value(true)
-                //                          .use_delimiter(false)
-                //                          .default_value("fatal")
-                //                          .possible_values(&[
-                //                              "fatal",
-                //                              "ignore",
-                //                          ])
+                .arg(
+                    Arg::with_name("command-failure")
+                        .long("command-failure")
+                        .takes_value(true)
+                        .use_delimiter(false)
+                        .default_value("fatal")
+                        .possible_values(["fatal", "ignore", "report", "collect"])
+                        .help("When a command fails: collect lists them all at the end"),
+                )
+                .arg(
+                    Arg::with_name("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .takes_value(true)
+                        .default_value("1")
+                        .validator(|v| match v.parse::<usize>() {
+                            Ok(jobs) if jobs > 0 => Ok(()),
+                            _ => Err("must be a positive number".to_string()),
+                        })
+                        .help("Run this many commands at once, buffering files to disk"),
+                )
                 .arg(
                     Arg::with_name("command")
                         .required(true)
@@ -198,6 +320,27 @@ fn real_main() -> u8 {
                 }
             }
         }
+        ("extract", matches) => {
+            let collision = match matches.value_of("on-collision").unwrap() {
+                "fail" => extract::Collision::Fail,
+                "skip" => extract::Collision::Skip,
+                "overwrite" => extract::Collision::Overwrite,
+                "rename" => extract::Collision::Rename,
+                _ => unreachable!(),
+            };
+            let dir = Path::new(matches.value_of("dir").unwrap());
+            let special_bits = matches.is_present("keep-special-bits");
+            if !extract(&mut from, dir, collision, special_bits) {
+                return 2;
+            }
+        }
+        ("tar", matches) => {
+            let stdout = io::stdout();
+            let separator = matches.value_of("separator").unwrap();
+            if !flatten(&mut from, stdout.lock(), separator) {
+                return 2;
+            }
+        }
         ("run", matches) => {
             let raw_command: Vec<&str> = matches.values_of("command").unwrap().collect();
             let as_dumb_line = raw_command.join(" ");
@@ -208,8 +351,18 @@ fn real_main() -> u8 {
                 raw_command
             };
 
-            if !direct_run(&mut from, &cmd) {
-                return 2;
+            let policy = match matches.value_of("command-failure").unwrap() {
+                "fatal" => run::FailurePolicy::Fatal,
+                "ignore" => run::FailurePolicy::Ignore,
+                "report" => run::FailurePolicy::Report,
+                "collect" => run::FailurePolicy::Collect,
+                _ => unreachable!(),
+            };
+            let jobs = matches.value_of("jobs").unwrap().parse().unwrap();
+
+            match direct_run(&mut from, &cmd, policy, jobs) {
+                0 => {}
+                code => return code,
             }
         }
         _ => unreachable!(),
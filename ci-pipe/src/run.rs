@@ -0,0 +1,295 @@
+use std::io;
+use std::io::Seek;
+use std::io::Write;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use ci_capnp::{FileEntry, Ownership};
+
+const NANOS: u64 = 1_000_000_000;
+
+/// What a command exiting unsuccessfully does to the rest of the run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Stop everything.
+    Fatal,
+    Ignore,
+    /// Say so on stderr, and carry on.
+    Report,
+    /// Carry on, then list every failure at the end, and fail overall.
+    Collect,
+}
+
+/// `ci-pipe run`: a command per file, with the file on its stdin, and the rest of the entry
+/// in `TAR_*` environment variables, as GNU tar's `--to-command` would.
+///
+/// With more than one job, the content has to be buffered, to a temp file, as the stream
+/// can't be read on until the command has taken it all.
+pub struct Runner<'c> {
+    cmd: &'c [&'c str],
+    policy: FailurePolicy,
+    jobs: usize,
+    /// In no particular order; whichever finishes first is reaped first.
+    running: Vec<(String, process::Child)>,
+    failures: Vec<(String, process::ExitStatus)>,
+}
+
+impl<'c> Runner<'c> {
+    pub fn new(cmd: &'c [&'c str], policy: FailurePolicy, jobs: usize) -> Runner<'c> {
+        assert!(jobs > 0, "at least one job");
+        Runner {
+            cmd,
+            policy,
+            jobs,
+            running: Vec::with_capacity(jobs),
+            failures: Vec::new(),
+        }
+    }
+
+    /// `content` must be exactly the entry's content.
+    pub fn run<R: io::Read>(&mut self, entry: &FileEntry, mut content: R) -> io::Result<()> {
+        let mut command = self.command(entry);
+        let name = crate::join_backwards(&entry.paths, "/ /");
+
+        if 1 == self.jobs {
+            let mut child = command.stdin(process::Stdio::piped()).spawn()?;
+            let written = io::copy(&mut content, child.stdin.as_mut().expect("piped"));
+            match written {
+                // the command doesn't want the rest; that's up to it
+                Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                other => {
+                    other?;
+                }
+            }
+            let status = child.wait()?;
+            return self.finished(name, status);
+        }
+
+        let mut buffer = tempfile::tempfile()?;
+        io::copy(&mut content, &mut buffer)?;
+        buffer.rewind()?;
+        let child = command.stdin(buffer).spawn()?;
+        self.running.push((name, child));
+
+        while self.running.len() >= self.jobs {
+            self.reap()?;
+        }
+        Ok(())
+    }
+
+    /// Wait for everything, and report on it, if we're collecting.
+    pub fn finish(mut self) -> io::Result<bool> {
+        while !self.running.is_empty() {
+            self.reap()?;
+        }
+
+        let mut stderr = io::stderr();
+        for (name, status) in &self.failures {
+            writeln!(stderr, "{}\t{}", describe(status), name)?;
+        }
+        Ok(self.failures.is_empty())
+    }
+
+    fn command(&self, entry: &FileEntry) -> process::Command {
+        let mut command = process::Command::new(self.cmd[0]);
+        command
+            .args(&self.cmd[1..])
+            .env("TAR_REALNAME", crate::join_backwards(&entry.paths, "/ /"))
+            .env("TAR_FILENAME", &entry.paths[0])
+            .env("TAR_SIZE", format!("{}", entry.len))
+            .stdout(process::Stdio::inherit())
+            .stderr(process::Stdio::inherit());
+
+        // the input's own name, then anything it's inside of, one per line
+        let containers: Vec<&str> = entry.paths[1..].iter().rev().map(|p| p.as_str()).collect();
+        command.env("TAR_CONTAINERS", containers.join("\n"));
+
+        let meta = &entry.meta;
+        if 0 != meta.mtime {
+            command.env(
+                "TAR_MTIME",
+                format!("{}.{:09}", meta.mtime / NANOS, meta.mtime % NANOS),
+            );
+        }
+        if let Ownership::Posix { mode, .. } = meta.ownership {
+            command.env("TAR_MODE", format!("{:04o}", mode & 0o7777));
+        }
+        command
+    }
+
+    /// Wait for any one of the running commands; if that's fatal, don't leave the rest behind.
+    fn reap(&mut self) -> io::Result<()> {
+        loop {
+            for i in 0..self.running.len() {
+                let status = match self.running[i].1.try_wait() {
+                    Ok(Some(status)) => status,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        self.abandon();
+                        return Err(e);
+                    }
+                };
+                let (name, _) = self.running.swap_remove(i);
+                let res = self.finished(name, status);
+                if res.is_err() {
+                    self.abandon();
+                }
+                return res;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn abandon(&mut self) {
+        for (_, mut child) in self.running.drain(..) {
+            // it may have exited already, which is fine
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    fn finished(&mut self, name: String, status: process::ExitStatus) -> io::Result<()> {
+        if status.success() {
+            return Ok(());
+        }
+
+        match self.policy {
+            FailurePolicy::Fatal => Err(io::Error::other(format!(
+                "command failed ({}) for {}",
+                describe(&status),
+                name
+            ))),
+            FailurePolicy::Ignore => Ok(()),
+            FailurePolicy::Report => writeln!(
+                io::stderr(),
+                "command failed ({}) for {}",
+                describe(&status),
+                name
+            ),
+            FailurePolicy::Collect => {
+                self.failures.push((name, status));
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn describe(status: &process::ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exit {}", code),
+        (None, Some(signal)) => format!("signal {}", signal),
+        (None, None) => status.to_string(),
+    }
+}
+
+#[cfg(not(unix))]
+fn describe(status: &process::ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exit {}", code),
+        None => status.to_string(),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::time::Instant;
+
+    use ci_capnp::{Container, ItemType, Meta};
+
+    use super::*;
+
+    fn entry(paths: &[&str]) -> FileEntry {
+        FileEntry {
+            len: 5,
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+            raw_paths: Vec::new(),
+            digests: Vec::new(),
+            content_follows: true,
+            meta: Meta {
+                atime: 0,
+                mtime: 1_500_000_000 * NANOS + 250_000_000,
+                ctime: 0,
+                btime: 0,
+                ownership: Ownership::Posix {
+                    user: None,
+                    group: None,
+                    mode: 0o104755,
+                },
+                item_type: ItemType::RegularFile,
+                container: Container::Unrecognised,
+                xattrs: HashMap::new(),
+                format_fields: HashMap::new(),
+            },
+        }
+    }
+
+    fn run(cmd: &[&str], policy: FailurePolicy, jobs: usize) -> io::Result<bool> {
+        let mut runner = Runner::new(cmd, policy, jobs);
+        runner.run(&entry(&["foo", "bar.tar", "input.tar.gz"]), &b"hello"[..])?;
+        runner.finish()
+    }
+
+    #[test]
+    fn environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = format!(
+            "cat > {0}/content; env | grep ^TAR_ | sort > {0}/env; printf %s \"$TAR_CONTAINERS\" > {0}/containers",
+            dir.path().display()
+        );
+        for jobs in [1, 2] {
+            assert!(run(&["sh", "-c", &script], FailurePolicy::Fatal, jobs).unwrap());
+            assert_eq!(
+                "hello",
+                fs::read_to_string(dir.path().join("content")).unwrap()
+            );
+            assert_eq!(
+                "input.tar.gz\nbar.tar",
+                fs::read_to_string(dir.path().join("containers")).unwrap()
+            );
+            let env = fs::read_to_string(dir.path().join("env")).unwrap();
+            for expected in [
+                "TAR_FILENAME=foo",
+                "TAR_MODE=4755",
+                "TAR_MTIME=1500000000.250000000",
+                "TAR_REALNAME=bar.tar/ /foo",
+                "TAR_SIZE=5",
+            ] {
+                assert!(env.lines().any(|line| line == expected), "{}", expected);
+            }
+        }
+    }
+
+    #[test]
+    fn policies() {
+        let fail = ["sh", "-c", "cat > /dev/null; exit 3"];
+        for jobs in [1, 2] {
+            assert!(run(&fail, FailurePolicy::Fatal, jobs).is_err());
+            assert!(run(&fail, FailurePolicy::Ignore, jobs).unwrap());
+            assert!(run(&fail, FailurePolicy::Report, jobs).unwrap());
+            assert!(!run(&fail, FailurePolicy::Collect, jobs).unwrap());
+        }
+        assert!(run(&["true"], FailurePolicy::Collect, 1).unwrap());
+    }
+
+    #[test]
+    fn jobs_reap_whichever_finishes() {
+        let cmd = [
+            "sh",
+            "-c",
+            "if [ a = \"$TAR_FILENAME\" ]; then sleep 5; else exit 1; fi",
+        ];
+        let start = Instant::now();
+        let mut runner = Runner::new(&cmd, FailurePolicy::Fatal, 2);
+        runner.run(&entry(&["a", "in"]), io::empty()).unwrap();
+
+        // the failure is noticed, and the slow one killed, without waiting for it
+        runner.run(&entry(&["b", "in"]), io::empty()).unwrap_err();
+        assert!(runner.running.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+}